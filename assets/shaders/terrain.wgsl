@@ -0,0 +1,98 @@
+#import bevy_pbr::mesh_view_bindings
+#import bevy_pbr::mesh_bindings
+#import bevy_pbr::mesh_functions
+
+#import bevy_pbr::pbr_types
+#import bevy_pbr::utils
+#import bevy_pbr::clustered_forward
+#import bevy_pbr::lighting
+#import bevy_pbr::shadows
+#import bevy_pbr::pbr_functions
+
+@group(1) @binding(0)
+var textures: texture_2d_array<f32>;
+@group(1) @binding(1)
+var textures_sampler: sampler;
+
+struct Fog {
+    color: vec4<f32>,
+    start: f32,
+    end: f32,
+    density: f32,
+};
+
+@group(1) @binding(2)
+var<uniform> fog: Fog;
+
+struct Vertex {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(3) layer: u32,
+    @location(4) occlusion: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(3) @interpolate(flat) layer: u32,
+    @location(4) occlusion: f32,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    out.world_position = mesh_position_local_to_world(mesh.model, vec4<f32>(vertex.position, 1.0));
+    out.world_normal = mesh_normal_local_to_world(vertex.normal);
+    out.uv = vertex.uv;
+    out.layer = vertex.layer;
+    out.occlusion = vertex.occlusion;
+    out.clip_position = mesh_position_world_to_clip(out.world_position);
+    return out;
+}
+
+struct FragmentInput {
+    @builtin(front_facing) is_front: bool,
+    @builtin(position) frag_coord: vec4<f32>,
+    @location(0) world_position: vec4<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(3) @interpolate(flat) layer: u32,
+    @location(4) occlusion: f32,
+};
+
+fn apply_fog(color: vec4<f32>, world_position: vec3<f32>) -> vec4<f32> {
+    let distance = length(world_position - view.world_position.xyz);
+    var amount = 0.0;
+    if (fog.density > 0.0) {
+        let d = distance * fog.density;
+        amount = 1.0 - exp(-d * d);
+    } else if (fog.end > fog.start) {
+        amount = clamp((distance - fog.start) / (fog.end - fog.start), 0.0, 1.0);
+    }
+    // the alpha is kept since whatever shows through translucent blocks is fogged as well
+    return vec4<f32>(mix(color.rgb, fog.color.rgb, amount), color.a);
+}
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    var pbr_input: PbrInput = pbr_input_new();
+
+    // the uv counts voxels, so the repeating sampler tiles a texture per voxel across merged quads
+    let color = textureSample(textures, textures_sampler, in.uv, i32(in.layer));
+    // interpolating the baked occlusion across the quad lights the voxels smoothly
+    pbr_input.material.base_color = vec4<f32>(color.rgb * in.occlusion, color.a);
+    // keep the alpha of the texture for translucent blocks; the opaque pass ignores it
+    pbr_input.material.flags = STANDARD_MATERIAL_FLAGS_ALPHA_MODE_BLEND;
+
+    pbr_input.frag_coord = in.frag_coord;
+    pbr_input.world_position = in.world_position;
+    pbr_input.world_normal = in.world_normal;
+    pbr_input.is_orthographic = view.projection[3].w == 1.0;
+    pbr_input.N = normalize(in.world_normal);
+    pbr_input.V = calculate_view(in.world_position, pbr_input.is_orthographic);
+
+    return apply_fog(tone_mapping(pbr(pbr_input)), in.world_position.xyz);
+}
@@ -0,0 +1,139 @@
+use crate::{chunk::ChunkShape, voxel::Voxel};
+use block_mesh::{
+    ndshape::ConstShape, FaceStrides, MergeStrategy, MergeVoxel, OrientedBlockFace, UnorientedQuad,
+    Voxel as _, VoxelVisibility,
+};
+
+/// Brightness of a vertex by the number of open voxels around it, from a corner enclosed by three voxels to the open
+const AO_LIGHT: [f32; 4] = [0.35, 0.55, 0.8, 1.0];
+
+/// Ambient occlusion of the face corners in the order of `OrientedBlockFace::quad_corners`, 0 darkest to 3 open
+pub type FaceAo = [u8; 4];
+
+/// Ambient occlusion of the face of the voxel at `index` from the voxels around the one in front of the face
+///
+/// `front` is the offset to the voxel in front of the face and `u`, `v` are the strides along the face.
+fn face_ao(voxels: &[Voxel], index: u32, front: u32, u: u32, v: u32) -> FaceAo {
+    let base = index.wrapping_add(front);
+    let occludes = |du: u32, dv: u32| {
+        let i = base
+            .wrapping_add(u.wrapping_mul(du))
+            .wrapping_add(v.wrapping_mul(dv));
+        (voxels[i as usize].get_visibility() == VoxelVisibility::Opaque) as u8
+    };
+
+    // u32::MAX steps backwards
+    [(u32::MAX, u32::MAX), (1, u32::MAX), (u32::MAX, 1), (1, 1)].map(|(du, dv)| {
+        let (side_u, side_v) = (occludes(du, 0), occludes(0, dv));
+        if side_u + side_v == 2 {
+            0
+        } else {
+            3 - side_u - side_v - occludes(du, dv)
+        }
+    })
+}
+
+/// Ambient occlusion of the corners of the quad; every face merged into the quad has the same
+pub fn quad_ao(voxels: &[Voxel], face: &OrientedBlockFace, quad: &UnorientedQuad) -> FaceAo {
+    // unit steps along the face from the corners of a single face
+    let [c0, c1, c2, _] = face
+        .quad_corners(&UnorientedQuad {
+            minimum: [0; 3],
+            width: 1,
+            height: 1,
+        })
+        .map(|c| c.to_array());
+    let stride = |c: [u32; 3]| ChunkShape::linearize([0, 1, 2].map(|a| c[a] - c0[a]));
+
+    let normal = face.signed_normal().to_array();
+    let front = ChunkShape::linearize(normal.map(|n| n.unsigned_abs()));
+    let front = if normal.iter().sum::<i32>() > 0 {
+        front
+    } else {
+        0u32.wrapping_sub(front)
+    };
+
+    face_ao(
+        voxels,
+        ChunkShape::linearize(quad.minimum),
+        front,
+        stride(c1),
+        stride(c2),
+    )
+}
+
+/// Brightness of the vertices
+pub fn ao_light(ao: FaceAo) -> [f32; 4] {
+    ao.map(|a| AO_LIGHT[a as usize])
+}
+
+/// Vertex indices of the quad starting at `start`, split along the diagonal that interpolates the occlusion evenly
+pub fn quad_indices(face: &OrientedBlockFace, ao: FaceAo, start: u32) -> [u32; 6] {
+    let indices = face.quad_mesh_indices(start);
+    if ao[0] + ao[3] < ao[1] + ao[2] {
+        // the other diagonal with the same winding
+        indices.map(|i| start + [1, 3, 0, 2][(i - start) as usize])
+    } else {
+        indices
+    }
+}
+
+/// Greedy merging of the faces of the same block with the same ambient occlusion
+pub struct AoMerger;
+
+impl AoMerger {
+    /// Same as the check of `greedy_quads`: not meshed yet and not hidden by the voxel in front
+    fn needs_mesh(voxels: &[Voxel], visited: &[bool], index: u32, front: u32) -> bool {
+        let voxel = voxels[index as usize].get_visibility();
+        if voxel == VoxelVisibility::Empty || visited[index as usize] {
+            return false;
+        }
+
+        match voxels[index.wrapping_add(front) as usize].get_visibility() {
+            VoxelVisibility::Empty => true,
+            VoxelVisibility::Translucent => voxel == VoxelVisibility::Opaque,
+            VoxelVisibility::Opaque => false,
+        }
+    }
+}
+
+impl MergeStrategy for AoMerger {
+    type Voxel = Voxel;
+
+    unsafe fn find_quad(
+        min_index: u32,
+        max_width: u32,
+        max_height: u32,
+        strides: &FaceStrides,
+        voxels: &[Voxel],
+        visited: &[bool],
+    ) -> (u32, u32) {
+        let FaceStrides {
+            u_stride,
+            v_stride,
+            visibility_offset: front,
+            ..
+        } = *strides;
+        let key = |i: u32| {
+            (
+                voxels[i as usize].merge_value(),
+                face_ao(voxels, i, front, u_stride, v_stride),
+            )
+        };
+        let quad_key = key(min_index);
+        let row_width = |start: u32, max: u32| {
+            (0..max)
+                .map(|k| start + k * u_stride)
+                .take_while(|&i| Self::needs_mesh(voxels, visited, i, front) && key(i) == quad_key)
+                .count() as u32
+        };
+
+        let width = row_width(min_index, max_width);
+        let mut height = 1;
+        while height < max_height && row_width(min_index + height * v_stride, width) == width {
+            height += 1;
+        }
+
+        (width, height)
+    }
+}
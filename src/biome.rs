@@ -0,0 +1,230 @@
+use crate::block::BlockId;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Biome {
+    Ocean,
+    Beach,
+    Plains,
+    Forest,
+    Desert,
+    Mountains,
+    Tundra,
+}
+
+/// Terrain level as `base + amplitude * local^exponent` of the local noise in [0, 1]
+struct HeightCurve {
+    base: f32,
+    amplitude: f32,
+    exponent: f32,
+}
+
+impl HeightCurve {
+    fn level(&self, local: f32) -> f32 {
+        self.base + self.amplitude * local.powf(self.exponent)
+    }
+}
+
+struct BiomeDef {
+    biome: Biome,
+    /// Temperature and moisture the biome is the most likely in; `None` if it's decided by continentalness
+    climate: Option<(f32, f32)>,
+    height: HeightCurve,
+    surface: BlockId,
+    subsurface: BlockId,
+    /// Fraction of the load distance visible through the fog
+    fog: f32,
+}
+
+const BIOMES: [BiomeDef; 7] = [
+    BiomeDef {
+        biome: Biome::Ocean,
+        climate: None,
+        height: HeightCurve {
+            base: 4.0,
+            amplitude: 12.0,
+            exponent: 1.0,
+        },
+        surface: BlockId::SAND,
+        subsurface: BlockId::SAND,
+        fog: 1.0,
+    },
+    BiomeDef {
+        biome: Biome::Beach,
+        climate: None,
+        height: HeightCurve {
+            base: 21.0,
+            amplitude: 3.0,
+            exponent: 1.0,
+        },
+        surface: BlockId::SAND,
+        subsurface: BlockId::SAND,
+        fog: 1.0,
+    },
+    BiomeDef {
+        biome: Biome::Plains,
+        climate: Some((0.5, 0.45)),
+        height: HeightCurve {
+            base: 22.0,
+            amplitude: 8.0,
+            exponent: 1.0,
+        },
+        surface: BlockId::GRASS,
+        subsurface: BlockId::DIRT,
+        fog: 1.0,
+    },
+    BiomeDef {
+        biome: Biome::Forest,
+        climate: Some((0.5, 0.65)),
+        height: HeightCurve {
+            base: 22.0,
+            amplitude: 12.0,
+            exponent: 1.0,
+        },
+        surface: BlockId::FOREST_GRASS,
+        subsurface: BlockId::DIRT,
+        fog: 0.6,
+    },
+    BiomeDef {
+        biome: Biome::Desert,
+        climate: Some((0.68, 0.3)),
+        height: HeightCurve {
+            base: 22.0,
+            amplitude: 10.0,
+            exponent: 2.0,
+        },
+        surface: BlockId::SAND,
+        subsurface: BlockId::SAND,
+        fog: 0.75,
+    },
+    BiomeDef {
+        biome: Biome::Mountains,
+        climate: None,
+        height: HeightCurve {
+            base: 30.0,
+            amplitude: 33.0,
+            exponent: 2.0,
+        },
+        surface: BlockId::STONE,
+        subsurface: BlockId::STONE,
+        fog: 1.0,
+    },
+    BiomeDef {
+        biome: Biome::Tundra,
+        climate: Some((0.3, 0.5)),
+        height: HeightCurve {
+            base: 24.0,
+            amplitude: 14.0,
+            exponent: 1.5,
+        },
+        surface: BlockId::SNOW,
+        subsurface: BlockId::DIRT,
+        fog: 0.5,
+    },
+];
+
+/// Mountains above the level are covered with snow
+const SNOW_LEVEL: i64 = 52;
+
+/// Spread of the land biomes in the temperature-moisture space; larger values blend wider
+const CLIMATE_SPREAD: f32 = 0.08;
+
+/// Min and max terrain levels any blend of the biomes can reach
+pub fn level_bounds() -> (i64, i64) {
+    BIOMES.iter().fold((i64::MAX, i64::MIN), |(lo, hi), def| {
+        let curve = &def.height;
+        let (min, max) = (curve.base, curve.base + curve.amplitude);
+        (lo.min(min.floor() as i64), hi.max(max.ceil() as i64))
+    })
+}
+
+/// Noise values in [0, 1] deciding the biome at a column
+#[derive(Clone, Copy, Debug)]
+pub struct Climate {
+    /// Low in oceans and high in mountains
+    pub continentalness: f32,
+    pub temperature: f32,
+    pub moisture: f32,
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+impl Climate {
+    /// Weights of the biomes in the order of `BIOMES`, summing up to 1
+    fn weights(&self) -> [f32; 7] {
+        let c = self.continentalness;
+        let ocean = 1.0 - smoothstep(0.45, 0.52, c);
+        let mountains = smoothstep(0.8, 0.9, c);
+        let coast = (1.0 - ocean - mountains).max(0.0);
+        let beach = coast * (1.0 - smoothstep(0.52, 0.58, c));
+        let land = coast - beach;
+
+        let mut weights = [0.0; 7];
+        let mut climate_total = 0.0;
+        for (w, def) in weights.iter_mut().zip(&BIOMES) {
+            if let Some((t, m)) = def.climate {
+                let d2 = (self.temperature - t).powi(2) + (self.moisture - m).powi(2);
+                *w = (-d2 / (CLIMATE_SPREAD * CLIMATE_SPREAD)).exp();
+                climate_total += *w;
+            }
+        }
+        for (w, def) in weights.iter_mut().zip(&BIOMES) {
+            *w = match def.biome {
+                Biome::Ocean => ocean,
+                Biome::Beach => beach,
+                Biome::Mountains => mountains,
+                _ => land * *w / climate_total.max(f32::EPSILON),
+            };
+        }
+
+        weights
+    }
+
+    /// Terrain level blended over the biomes
+    pub fn level(&self, local: f32) -> i64 {
+        self.weights()
+            .iter()
+            .zip(&BIOMES)
+            .map(|(w, def)| w * def.height.level(local))
+            .sum::<f32>() as i64
+    }
+
+    /// Pick a biome by the weights with `dither` in [0, 1) so that borders are mixed instead of a sharp line
+    pub fn biome(&self, dither: f32) -> Biome {
+        let mut acc = 0.0;
+        for (w, def) in self.weights().iter().zip(&BIOMES) {
+            acc += w;
+            if dither < acc {
+                return def.biome;
+            }
+        }
+        Biome::Plains
+    }
+}
+
+impl Biome {
+    fn def(self) -> &'static BiomeDef {
+        BIOMES.iter().find(|d| d.biome == self).unwrap()
+    }
+
+    /// Block of the top voxel at the terrain level
+    pub fn surface(self, level: i64) -> BlockId {
+        if self == Biome::Mountains && level >= SNOW_LEVEL {
+            BlockId::SNOW
+        } else {
+            self.def().surface
+        }
+    }
+
+    /// Block of the few voxels below the surface
+    pub fn subsurface(self) -> BlockId {
+        self.def().subsurface
+    }
+
+    /// Fraction of the load distance visible through the fog
+    pub fn fog(self) -> f32 {
+        self.def().fog
+    }
+}
@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+
+/// Index of a block in the registry
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct BlockId(u16);
+
+impl BlockId {
+    pub const AIR: BlockId = BlockId(0);
+    pub const STONE: BlockId = BlockId(1);
+    pub const DIRT: BlockId = BlockId(2);
+    pub const GRASS: BlockId = BlockId(3);
+    pub const FOREST_GRASS: BlockId = BlockId(4);
+    pub const SAND: BlockId = BlockId(5);
+    pub const SNOW: BlockId = BlockId(6);
+    pub const WATER: BlockId = BlockId(7);
+
+    /// Definition of the block; unknown ids, e.g. from newer saves, fall back to air
+    pub fn block(self) -> &'static Block {
+        BLOCKS.get(self.0 as usize).unwrap_or(&BLOCKS[0])
+    }
+}
+
+/// Properties of a kind of block
+#[derive(Debug)]
+pub struct Block {
+    pub name: &'static str,
+    /// Texture layers of the faces
+    pub textures: BlockTextures,
+    /// Collides with bodies and can be dug
+    pub solid: bool,
+    /// Faces behind the block are visible
+    pub transparent: bool,
+    /// Bodies in the block float and swim
+    pub liquid: bool,
+    /// Seconds to dig the block
+    pub hardness: f32,
+}
+
+/// Layers of the block texture array drawn on the faces of a block
+#[derive(Debug)]
+pub struct BlockTextures {
+    pub top: u32,
+    pub side: u32,
+    pub bottom: u32,
+}
+
+impl BlockTextures {
+    /// Same layer on every face
+    const fn all(layer: u32) -> Self {
+        Self {
+            top: layer,
+            side: layer,
+            bottom: layer,
+        }
+    }
+
+    /// Layer of the face with the normal
+    pub fn face(&self, normal: [i32; 3]) -> u32 {
+        match normal[1] {
+            1 => self.top,
+            -1 => self.bottom,
+            _ => self.side,
+        }
+    }
+}
+
+/// Number of layers in `assets/textures/blocks.png`, stacked vertically
+pub const TEXTURE_LAYERS: u32 = 9;
+
+/// Registry of the blocks indexed by `BlockId`
+static BLOCKS: [Block; 8] = [
+    Block {
+        name: "air",
+        textures: BlockTextures::all(0),
+        solid: false,
+        transparent: true,
+        liquid: false,
+        hardness: 0.0,
+    },
+    Block {
+        name: "stone",
+        textures: BlockTextures::all(0),
+        solid: true,
+        transparent: false,
+        liquid: false,
+        hardness: 0.8,
+    },
+    Block {
+        name: "dirt",
+        textures: BlockTextures::all(1),
+        solid: true,
+        transparent: false,
+        liquid: false,
+        hardness: 0.3,
+    },
+    Block {
+        name: "grass",
+        textures: BlockTextures {
+            top: 2,
+            side: 3,
+            bottom: 1,
+        },
+        solid: true,
+        transparent: false,
+        liquid: false,
+        hardness: 0.3,
+    },
+    Block {
+        name: "forest grass",
+        textures: BlockTextures {
+            top: 4,
+            side: 5,
+            bottom: 1,
+        },
+        solid: true,
+        transparent: false,
+        liquid: false,
+        hardness: 0.3,
+    },
+    Block {
+        name: "sand",
+        textures: BlockTextures::all(6),
+        solid: true,
+        transparent: false,
+        liquid: false,
+        hardness: 0.2,
+    },
+    Block {
+        name: "snow",
+        textures: BlockTextures::all(7),
+        solid: true,
+        transparent: false,
+        liquid: false,
+        hardness: 0.1,
+    },
+    Block {
+        name: "water",
+        textures: BlockTextures::all(8),
+        solid: false,
+        transparent: true,
+        liquid: true,
+        hardness: 0.0,
+    },
+];
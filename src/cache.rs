@@ -0,0 +1,148 @@
+use crate::{chunk::ChunkMesh, lod::LodChunk, voxel::Voxel};
+use bevy::prelude::*;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+// Default memory budget of the cache in bytes
+#[cfg(target_arch = "wasm32")]
+pub const CACHE_BUDGET: usize = 64 << 20;
+#[cfg(not(target_arch = "wasm32"))]
+pub const CACHE_BUDGET: usize = 512 << 20;
+
+/// Rough size of an entry besides the voxels and the meshes
+const ENTRY_OVERHEAD: usize = 128;
+
+#[derive(Default)]
+struct Entry {
+    /// Generated voxels before modifications
+    voxels: Option<Arc<Vec<Voxel>>>,
+    /// Meshes with the revision of the modifications they reflect
+    mesh: Option<(u64, ChunkMesh)>,
+    used: u64,
+}
+
+impl Entry {
+    fn size(&self) -> usize {
+        let voxels = self
+            .voxels
+            .as_ref()
+            .map(|v| v.len() * std::mem::size_of::<Voxel>())
+            .unwrap_or(0);
+        let mesh = match &self.mesh {
+            Some((_, m)) => [&m.opaque, &m.translucent]
+                .into_iter()
+                .flatten()
+                .map(mesh_size)
+                .sum(),
+            None => 0,
+        };
+
+        ENTRY_OVERHEAD + voxels + mesh
+    }
+}
+
+fn mesh_size(mesh: &Mesh) -> usize {
+    let attributes: usize = mesh.attributes().map(|(_, v)| v.get_bytes().len()).sum();
+    let indices = mesh.get_index_buffer_bytes().map(|b| b.len()).unwrap_or(0);
+    attributes + indices
+}
+
+struct Lru {
+    entries: HashMap<LodChunk, Entry>,
+    /// Entries by the tick they're used last
+    order: BTreeMap<u64, LodChunk>,
+    tick: u64,
+    size: usize,
+    budget: usize,
+}
+
+impl Lru {
+    fn get(&mut self, chunk: &LodChunk) -> Option<&Entry> {
+        let tick = self.tick;
+        let entry = self.entries.get_mut(chunk)?;
+        self.order.remove(&entry.used);
+        self.order.insert(tick, chunk.clone());
+        entry.used = tick;
+        self.tick += 1;
+
+        Some(entry)
+    }
+
+    fn update(&mut self, chunk: &LodChunk, f: impl FnOnce(&mut Entry)) {
+        let tick = self.tick;
+        self.tick += 1;
+
+        let old_size = match self.entries.get(chunk) {
+            Some(e) => {
+                self.order.remove(&e.used);
+                e.size()
+            }
+            None => 0,
+        };
+        let entry = self.entries.entry(chunk.clone()).or_default();
+        f(entry);
+        entry.used = tick;
+        self.size = self.size - old_size + entry.size();
+        self.order.insert(tick, chunk.clone());
+
+        // least recently used first
+        while self.size > self.budget {
+            let chunk = match self.order.pop_first() {
+                Some((_, c)) => c,
+                None => break,
+            };
+            if let Some(entry) = self.entries.remove(&chunk) {
+                self.size -= entry.size();
+            }
+        }
+    }
+}
+
+/// Voxels and meshes of the recently generated chunks, evicted least recently used first beyond the memory budget
+///
+/// Shared by the chunk generation tasks.
+#[derive(Clone)]
+pub struct ChunkCache(Arc<Mutex<Lru>>);
+
+impl ChunkCache {
+    pub fn new(budget: usize) -> Self {
+        Self(Arc::new(Mutex::new(Lru {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            size: 0,
+            budget,
+        })))
+    }
+
+    /// Generated voxels of the chunk before modifications
+    pub fn voxels(&self, chunk: &LodChunk) -> Option<Arc<Vec<Voxel>>> {
+        self.0.lock().unwrap().get(chunk)?.voxels.clone()
+    }
+
+    /// Meshes of the chunk if they reflect the revision of the modifications
+    pub fn mesh(&self, chunk: &LodChunk, revision: u64) -> Option<ChunkMesh> {
+        match &self.0.lock().unwrap().get(chunk)?.mesh {
+            Some((r, mesh)) if *r == revision => Some(mesh.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn insert_voxels(&self, chunk: &LodChunk, voxels: Arc<Vec<Voxel>>) {
+        self.0
+            .lock()
+            .unwrap()
+            .update(chunk, |e| e.voxels = Some(voxels));
+    }
+
+    pub fn insert_mesh(&self, chunk: &LodChunk, revision: u64, mesh: ChunkMesh) {
+        self.0.lock().unwrap().update(chunk, |e| {
+            // tasks may finish out of order
+            if !matches!(&e.mesh, Some((r, _)) if *r > revision) {
+                e.mesh = Some((revision, mesh));
+            }
+        });
+    }
+}
@@ -0,0 +1,258 @@
+use crate::{
+    controller,
+    input::{Action, ActionState},
+    player::Player,
+    terrain::{LoadShape, Loader},
+};
+use bevy::{
+    input::mouse::{MouseMotion, MouseScrollUnit, MouseWheel},
+    prelude::*,
+};
+use bevy_rapier3d::prelude::*;
+use std::f32::consts::FRAC_PI_2;
+
+/// Radians per pixel of mouse motion
+const SENSITIVITY: f32 = 0.003;
+/// Range of the distance of the orbit camera from the player
+const MIN_DISTANCE: f32 = 2.0;
+const MAX_DISTANCE: f32 = 60.0;
+/// Change of the distance per line scrolled
+const ZOOM_RATE: f32 = 0.1;
+/// Radius of the sphere kept clear of the terrain around the orbit camera
+const CAMERA_RADIUS: f32 = 0.3;
+/// Height of the eyes above the center of the player
+const EYE_HEIGHT: f32 = 0.6;
+/// Free-fly speed in m/s, doubled while running
+const FLY_SPEED: f32 = 20.0;
+
+/// How the camera follows the player; switched with C
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraMode {
+    /// Orbits the player; drag with the middle mouse button to orbit and scroll to zoom
+    Orbit,
+    /// From the eyes of the player, looking around with the grabbed cursor
+    FirstPerson,
+    /// Flies freely away from the player, which stands still; loads the terrain around the camera as well
+    FreeFly,
+}
+
+impl CameraMode {
+    fn next(self) -> Self {
+        match self {
+            CameraMode::Orbit => CameraMode::FirstPerson,
+            CameraMode::FirstPerson => CameraMode::FreeFly,
+            CameraMode::FreeFly => CameraMode::Orbit,
+        }
+    }
+
+    /// The cursor is grabbed to look around
+    fn grabs_cursor(self) -> bool {
+        self != CameraMode::Orbit
+    }
+}
+
+#[derive(Component)]
+pub struct CameraController {
+    pub mode: CameraMode,
+    /// Rotation around the Y axis in radians
+    pub yaw: f32,
+    /// Rotation above the horizon in radians
+    pub pitch: f32,
+    /// Distance of the orbit camera from the player unless the terrain is in between
+    pub distance: f32,
+}
+
+impl Default for CameraController {
+    fn default() -> Self {
+        Self {
+            mode: CameraMode::Orbit,
+            yaw: 0.5,
+            pitch: -0.6,
+            distance: 20.0,
+        }
+    }
+}
+
+impl CameraController {
+    pub fn rotation(&self) -> Quat {
+        Quat::from_euler(EulerRot::YXZ, self.yaw, self.pitch, 0.0)
+    }
+}
+
+pub struct CameraPlugin;
+
+impl Plugin for CameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(create_camera_system)
+            .add_system(switch_mode_system)
+            .add_system(look_system.after(switch_mode_system))
+            .add_system(
+                follow_player_system
+                    .after(look_system)
+                    .after(controller::move_character_system),
+            )
+            .add_system(fly_system.after(look_system));
+    }
+}
+
+fn create_camera_system(mut commands: Commands) {
+    commands
+        .spawn_bundle(Camera3dBundle {
+            // far enough to see the coarsest terrain
+            projection: PerspectiveProjection {
+                far: 2000.0,
+                ..default()
+            }
+            .into(),
+            ..default()
+        })
+        .insert(CameraController::default());
+}
+
+fn cursor_grabbed(windows: &Windows) -> bool {
+    windows.get_primary().is_some_and(|w| w.cursor_locked())
+}
+
+fn grab_cursor(windows: &mut Windows, grab: bool) {
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_cursor_lock_mode(grab);
+        window.set_cursor_visibility(!grab);
+    }
+}
+
+/// Switch the mode and release or grab the cursor again
+fn switch_mode_system(
+    mut commands: Commands,
+    actions: Res<ActionState>,
+    mut windows: ResMut<Windows>,
+    mut controllers: Query<(Entity, &mut CameraController)>,
+) {
+    for (entity, mut controller) in &mut controllers {
+        if actions.just_pressed(Action::SwitchCamera) {
+            controller.mode = controller.mode.next();
+            grab_cursor(&mut windows, controller.mode.grabs_cursor());
+
+            // the player keeps its own loader so that the terrain under it stays
+            if controller.mode == CameraMode::FreeFly {
+                commands
+                    .entity(entity)
+                    .insert(Loader::new(LoadShape::default()));
+            } else {
+                commands.entity(entity).remove::<Loader>();
+            }
+        }
+
+        if actions.just_pressed(Action::GrabCursor) && controller.mode.grabs_cursor() {
+            let grabbed = cursor_grabbed(&windows);
+            grab_cursor(&mut windows, !grabbed);
+        }
+    }
+}
+
+/// Rotate by the look actions, and by the mouse while the cursor is grabbed or by dragging in orbit mode
+fn look_system(
+    time: Res<Time>,
+    windows: Res<Windows>,
+    actions: Res<ActionState>,
+    mut motion: EventReader<MouseMotion>,
+    mut wheel: EventReader<MouseWheel>,
+    mut controllers: Query<&mut CameraController>,
+) {
+    let delta = motion.iter().fold(Vec2::ZERO, |sum, m| sum + m.delta);
+    let scroll: f32 = wheel
+        .iter()
+        .map(|w| match w.unit {
+            MouseScrollUnit::Line => w.y,
+            // about a line
+            MouseScrollUnit::Pixel => w.y / 50.0,
+        })
+        .sum();
+
+    let grabbed = cursor_grabbed(&windows);
+    for mut controller in &mut controllers {
+        let looking = match controller.mode {
+            CameraMode::Orbit => actions.pressed(Action::Orbit),
+            CameraMode::FirstPerson | CameraMode::FreeFly => grabbed,
+        };
+        let mut turn = actions.look * time.delta_seconds();
+        if looking {
+            turn += Vec2::new(delta.x, -delta.y) * SENSITIVITY;
+        }
+        controller.yaw -= turn.x;
+        controller.pitch = (controller.pitch + turn.y).clamp(-FRAC_PI_2 + 0.01, FRAC_PI_2 - 0.01);
+
+        if controller.mode == CameraMode::Orbit {
+            controller.distance = (controller.distance * (1.0 - scroll * ZOOM_RATE))
+                .clamp(MIN_DISTANCE, MAX_DISTANCE);
+        }
+    }
+}
+
+/// Place the camera around the player in orbit and first-person modes
+fn follow_player_system(
+    context: Res<RapierContext>,
+    players: Query<(Entity, &Transform), With<Player>>,
+    mut cameras: Query<(&CameraController, &mut Transform), Without<Player>>,
+) {
+    let (player, player_transform) = match players.iter().next() {
+        Some(p) => p,
+        None => return,
+    };
+
+    for (controller, mut transform) in &mut cameras {
+        let rotation = controller.rotation();
+        let translation = match controller.mode {
+            CameraMode::Orbit => {
+                let target = player_transform.translation;
+                let dir = rotation * Vec3::Z;
+                // pulled in front of the terrain between the player and the camera
+                let filter = QueryFilter::only_fixed().exclude_rigid_body(player);
+                let ball = Collider::ball(CAMERA_RADIUS);
+                let distance = context
+                    .cast_shape(
+                        target,
+                        Quat::IDENTITY,
+                        dir,
+                        &ball,
+                        controller.distance,
+                        filter,
+                    )
+                    .map_or(controller.distance, |(_, hit)| hit.toi);
+                target + dir * distance
+            }
+            CameraMode::FirstPerson => player_transform.translation + Vec3::Y * EYE_HEIGHT,
+            CameraMode::FreeFly => continue,
+        };
+
+        *transform = Transform {
+            translation,
+            rotation,
+            ..default()
+        };
+    }
+}
+
+/// Fly toward the view by the movement and up and down by the fly actions in free-fly mode
+fn fly_system(
+    time: Res<Time>,
+    actions: Res<ActionState>,
+    mut cameras: Query<(&CameraController, &mut Transform)>,
+) {
+    for (controller, mut transform) in &mut cameras {
+        if controller.mode != CameraMode::FreeFly {
+            continue;
+        }
+
+        let rotation = controller.rotation();
+        let dir = rotation * Vec3::new(actions.movement.x, 0.0, actions.movement.y)
+            + Vec3::Y * actions.axis(Action::FlyUp, Action::FlyDown);
+        let speed = if actions.pressed(Action::Sprint) {
+            FLY_SPEED * 2.0
+        } else {
+            FLY_SPEED
+        };
+
+        transform.translation += dir.clamp_length_max(1.0) * speed * time.delta_seconds();
+        transform.rotation = rotation;
+    }
+}
@@ -0,0 +1,98 @@
+use crate::map::{overhang_cfg, tunnel_cfg, GenConfig, ProcGen};
+use serde::{Deserialize, Serialize};
+
+/// Parameters of caves and overhangs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaveConfig {
+    /// Tunnels run along the intersection of two noise fields seeded `seed` and `seed + 1`
+    pub tunnel: GenConfig,
+    /// Radius of tunnels in the noise space; 0 disables caves
+    pub density: f32,
+    /// Caves are carved between the levels
+    pub min_y: i64,
+    pub max_y: i64,
+    /// Distorts the terrain surface to form overhangs and arches
+    pub overhang: GenConfig,
+    /// Max distortion of the surface in voxels; 0 disables overhangs
+    pub overhang_amplitude: f32,
+}
+
+impl Default for CaveConfig {
+    fn default() -> Self {
+        Self {
+            tunnel: tunnel_cfg(),
+            density: 0.04,
+            min_y: 1,
+            max_y: 48,
+            overhang: overhang_cfg(),
+            overhang_amplitude: 4.0,
+        }
+    }
+}
+
+/// Carves caves and overhangs into the terrain with 3D noise
+pub struct CaveGen {
+    tunnels: (ProcGen, ProcGen),
+    overhang: ProcGen,
+    density: f32,
+    min_y: i64,
+    max_y: i64,
+    overhang_amplitude: f32,
+}
+
+impl CaveGen {
+    pub fn new(cfg: CaveConfig) -> Self {
+        let second = GenConfig {
+            seed: cfg.tunnel.seed.wrapping_add(1),
+            ..cfg.tunnel.clone()
+        };
+
+        Self {
+            tunnels: (ProcGen::new(cfg.tunnel), ProcGen::new(second)),
+            overhang: ProcGen::new(cfg.overhang),
+            density: cfg.density,
+            min_y: cfg.min_y,
+            max_y: cfg.max_y,
+            overhang_amplitude: cfg.overhang_amplitude,
+        }
+    }
+
+    /// Max distance the surface is moved by overhangs
+    pub fn overhang_amplitude(&self) -> f32 {
+        self.overhang_amplitude
+    }
+
+    /// Tunnels can be carved somewhere between the levels
+    pub fn carves(&self, min_y: i64, max_y: i64) -> bool {
+        self.density > 0.0 && min_y <= self.max_y && self.min_y <= max_y
+    }
+
+    /// Distance from the voxel to the terrain surface, distorted by the overhang noise; negative above the surface
+    pub fn depth(&self, x: i64, y: i64, z: i64, level: i64) -> f32 {
+        let depth = (level - y) as f32;
+
+        if depth.abs() > self.overhang_amplitude {
+            // the distortion can't flip the voxel
+            return depth;
+        }
+
+        depth + self.overhang_amplitude * (self.overhang.gen3(x, y, z) * 2.0 - 1.0)
+    }
+
+    /// The voxel is hollowed out by a tunnel
+    pub fn is_carved(&self, x: i64, y: i64, z: i64) -> bool {
+        if y < self.min_y || y > self.max_y || self.density <= 0.0 {
+            return false;
+        }
+
+        // squash vertically so that tunnels run rather horizontally
+        let a = self.tunnels.0.gen3(x, y * 2, z) - 0.5;
+        if a.abs() >= self.density {
+            return false;
+        }
+        let b = self.tunnels.1.gen3(x, y * 2, z) - 0.5;
+
+        a * a + b * b < self.density * self.density
+    }
+}
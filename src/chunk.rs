@@ -4,18 +4,24 @@ use bevy::{
     render::mesh::{Indices, PrimitiveTopology, VertexAttributeValues},
 };
 use block_mesh::{
-    greedy_quads,
+    greedy_quads_with_merge_strategy,
     ndshape::{ConstShape, ConstShape3u32},
-    GreedyQuadsBuffer, RIGHT_HANDED_Y_UP_CONFIG,
+    GreedyQuadsBuffer, OrientedBlockFace, UnorientedQuad, RIGHT_HANDED_Y_UP_CONFIG,
 };
 
-use crate::voxel::Voxel;
+use crate::{
+    ao::{ao_light, quad_ao, quad_indices, AoMerger, FaceAo},
+    edit::{PaddedEdits, VoxelEdits},
+    generator::{Occupancy, TerrainGen},
+    render::{ATTRIBUTE_OCCLUSION, ATTRIBUTE_TEXTURE_LAYER},
+    voxel::Voxel,
+};
 
 // 32 x 64 x 32 voxels in a chunk
 #[cfg(target_arch = "wasm32")]
-const CHUNK_VOXELS: UVec3 = UVec3::new(8, 8, 8);
+pub const CHUNK_VOXELS: UVec3 = UVec3::new(8, 8, 8);
 #[cfg(not(target_arch = "wasm32"))]
-const CHUNK_VOXELS: UVec3 = UVec3::new(32, 32, 32);
+pub const CHUNK_VOXELS: UVec3 = UVec3::new(32, 32, 32);
 
 // block mesh parameters; +2 of chunk size as block-mesh requires 1-voxel boundary padding for each side
 const CHUNK_SHAPE_SIZE_X: u32 = CHUNK_VOXELS.x + 2;
@@ -42,14 +48,32 @@ impl Chunk {
         Self { x, y, z }
     }
 
-    pub fn from_world_coord(coord: Vec3) -> Self {
-        let chunk_coord = coord / Self::size();
+    /// Chunk containing the voxel and the voxel coordinate local to the chunk
+    pub fn from_voxel_coord(x: i64, y: i64, z: i64) -> (Self, [u32; 3]) {
+        let size = (
+            CHUNK_VOXELS.x as i64,
+            CHUNK_VOXELS.y as i64,
+            CHUNK_VOXELS.z as i64,
+        );
+        let chunk = Self::new(
+            x.div_euclid(size.0),
+            y.div_euclid(size.1),
+            z.div_euclid(size.2),
+        );
+        let local = [
+            x.rem_euclid(size.0) as u32,
+            y.rem_euclid(size.1) as u32,
+            z.rem_euclid(size.2) as u32,
+        ];
 
-        Self {
-            x: chunk_coord.x as i64,
-            y: chunk_coord.y as i64,
-            z: chunk_coord.z as i64,
-        }
+        (chunk, local)
+    }
+
+    /// Voxel coordinate containing the position in the world coordinate
+    pub fn voxel_coord_from_world(coord: Vec3) -> (i64, i64, i64) {
+        let coord = (coord / Self::voxel_size()).floor();
+
+        (coord.x as i64, coord.y as i64, coord.z as i64)
     }
 
     /// Size in the world coordiate
@@ -61,191 +85,300 @@ impl Chunk {
         ) * Self::voxel_size()
     }
 
-    /// Position in the world coordinate
-    pub fn position(&self) -> Vec3 {
-        Vec3::new(self.x as f32, self.y as f32, self.z as f32) * Self::size()
-    }
-
     /// Length of voxel edge
     pub fn voxel_size() -> f32 {
         1.0
     }
 
-    /// Avoid unnecessary drawing
-    pub fn is_empty(&self) -> bool {
-        // TODO: Find more generic approach
-        self.y < 0 || self.y >= 64 / (CHUNK_VOXELS.y as i64)
+    /// Range of the voxel coordinate in y
+    pub fn voxel_range_y(&self) -> (i64, i64) {
+        let (_, base_y, _) = self.voxel_coord();
+        (base_y, base_y + CHUNK_VOXELS.y as i64 - 1)
     }
 
-    pub fn generate_mesh(&self) -> Option<Mesh> {
-        let voxels: Vec<_> = (0..ChunkShape::SIZE)
-            .map(|i| {
-                let [x, y, z] = ChunkShape::delinearize(i);
+    /// The generated voxels may have surfaces to draw, including the faces against the padding
+    pub fn has_surface(&self, gen: &TerrainGen) -> bool {
+        let (min_y, max_y) = self.voxel_range_y();
 
-                if (x == 0 || x == CHUNK_SHAPE_SIZE_X - 1)
-                    || (y == 0 || y == CHUNK_SHAPE_SIZE_Y - 1)
-                    || (z == 0 || z == CHUNK_SHAPE_SIZE_Z - 1)
-                {
-                    // padding
-                    return Voxel::EMPTY;
-                }
+        match gen.column_occupancy((min_y - 1, max_y + 1), &gen.heightmap(self)) {
+            Occupancy::Empty => false,
+            Occupancy::Mixed => true,
+            // lower terrain next to the chunk exposes its sides
+            Occupancy::Full => [(-1, 0), (1, 0), (0, -1), (0, 1)].iter().any(|(dx, dz)| {
+                let neighbour = Chunk::new(self.x + dx, self.y, self.z + dz);
+                gen.column_occupancy((min_y, max_y), &gen.heightmap(&neighbour)) != Occupancy::Full
+            }),
+        }
+    }
+
+    /// Generated voxels laid out in `ChunkShape` before modifications, with the neighbouring voxels in the padding
+    pub fn generate_voxels(&self, gen: &TerrainGen) -> Vec<Voxel> {
+        let heightmap = gen.heightmap(self);
+        let (base_x, base_y, base_z) = self.voxel_coord();
 
-                let (base_x, base_y, base_z) = self.voxel_coord();
-                let (x, y, z) = (base_x + x as i64, base_y + y as i64, base_z + z as i64);
-                generate_voxels(x - 1, y - 1, z - 1)
+        // the padding columns belong to the neighbours; sampled without caching their height maps
+        let columns: Vec<_> = (0..CHUNK_SHAPE_SIZE_Z)
+            .flat_map(|z| (0..CHUNK_SHAPE_SIZE_X).map(move |x| (x, z)))
+            .map(|(x, z)| {
+                if (1..=CHUNK_VOXELS.x).contains(&x) && (1..=CHUNK_VOXELS.z).contains(&z) {
+                    heightmap.column(x - 1, z - 1)
+                } else {
+                    gen.column(base_x + x as i64 - 1, base_z + z as i64 - 1)
+                }
             })
             .collect();
-        if voxels.iter().all(|v| v.is_empty()) {
-            return None;
+
+        (0..ChunkShape::SIZE)
+            .map(|i| {
+                let [x, y, z] = ChunkShape::delinearize(i);
+                gen.voxel(
+                    base_x + x as i64 - 1,
+                    base_y + y as i64 - 1,
+                    base_z + z as i64 - 1,
+                    columns[(z * CHUNK_SHAPE_SIZE_X + x) as usize],
+                )
+            })
+            .collect()
+    }
+
+    fn voxel_coord(&self) -> (i64, i64, i64) {
+        (
+            self.x * CHUNK_VOXELS.x as i64,
+            self.y * CHUNK_VOXELS.y as i64,
+            self.z * CHUNK_VOXELS.z as i64,
+        )
+    }
+}
+
+/// Voxel at the voxel coordinate reflecting the modifications
+pub fn voxel_at(gen: &TerrainGen, edits: &VoxelEdits, x: i64, y: i64, z: i64) -> Voxel {
+    if let Some(voxel) = edits.voxel(x, y, z) {
+        return voxel;
+    }
+
+    let (chunk, [lx, _, lz]) = Chunk::from_voxel_coord(x, y, z);
+    gen.voxel(x, y, z, gen.heightmap(&chunk).column(lx, lz))
+}
+
+/// Overwrite the voxels laid out in `ChunkShape` with the modifications
+pub fn apply_edits(voxels: &mut [Voxel], edits: &PaddedEdits) {
+    for (p, voxel) in edits.iter() {
+        voxels[ChunkShape::linearize(p) as usize] = voxel;
+    }
+}
+
+/// Meshes of the voxels split by the render pass
+#[derive(Clone, Default)]
+pub struct ChunkMesh {
+    /// Opaque voxels; also used for the collider
+    pub opaque: Option<Mesh>,
+    /// Translucent voxels such as water, drawn with alpha blending
+    pub translucent: Option<Mesh>,
+}
+
+/// Build meshes from the voxels laid out in `ChunkShape` with the edge length of a voxel
+///
+/// `empty_padding` tells that the padding holds no neighbouring voxels, so the translucent faces against it are
+/// dropped not to wall water at every border.
+pub fn mesh_voxels(voxels: &[Voxel], voxel_size: f32, empty_padding: bool) -> ChunkMesh {
+    if voxels.iter().all(|v| v.is_empty()) {
+        return ChunkMesh::default();
+    }
+
+    let mut buffer = GreedyQuadsBuffer::new(voxels.len());
+
+    // faces with different occlusion aren't merged so that it can be baked into the vertices
+    greedy_quads_with_merge_strategy::<_, _, AoMerger>(
+        voxels,
+        &ChunkShape {},
+        CHUNK_SHAPE_MIN_BUF,
+        CHUNK_SHAPE_MAX_BUF,
+        &RIGHT_HANDED_Y_UP_CONFIG.faces,
+        &mut buffer,
+    );
+
+    let mut opaque = MeshBuilder::default();
+    let mut translucent = MeshBuilder::default();
+
+    for (group, face) in buffer
+        .quads
+        .groups
+        .into_iter()
+        .zip(RIGHT_HANDED_Y_UP_CONFIG.faces)
+    {
+        for quad in group.into_iter() {
+            let voxel = voxels[ChunkShape::linearize(quad.minimum) as usize];
+            let ao = quad_ao(voxels, &face, &quad);
+
+            if !voxel.block().transparent {
+                opaque.push(&face, &quad, voxel, ao, voxel_size);
+                continue;
+            }
+
+            let normal = face.signed_normal();
+            let max = [
+                CHUNK_SHAPE_SIZE_X - 1,
+                CHUNK_SHAPE_SIZE_Y - 1,
+                CHUNK_SHAPE_SIZE_Z - 1,
+            ];
+            let on_border = empty_padding
+                && (0..3).any(|a| {
+                    let n = quad.minimum[a] as i32 + normal[a];
+                    normal[a] != 0 && (n == 0 || n == max[a] as i32)
+                });
+            if !on_border {
+                translucent.push(&face, &quad, voxel, ao, voxel_size);
+            }
         }
+    }
+
+    ChunkMesh {
+        opaque: opaque.build(),
+        translucent: translucent.build(),
+    }
+}
 
-        let mut buffer = GreedyQuadsBuffer::new(voxels.len());
+#[derive(Default)]
+struct MeshBuilder {
+    indices: Vec<u32>,
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    uvs: Vec<[f32; 2]>,
+    layers: Vec<u32>,
+    occlusion: Vec<f32>,
+}
 
-        greedy_quads(
-            &voxels,
-            &ChunkShape {},
-            CHUNK_SHAPE_MIN_BUF,
-            CHUNK_SHAPE_MAX_BUF,
-            &RIGHT_HANDED_Y_UP_CONFIG.faces,
-            &mut buffer,
+impl MeshBuilder {
+    fn push(
+        &mut self,
+        face: &OrientedBlockFace,
+        quad: &UnorientedQuad,
+        voxel: Voxel,
+        ao: FaceAo,
+        voxel_size: f32,
+    ) {
+        self.indices
+            .extend_from_slice(&quad_indices(face, ao, self.positions.len() as u32));
+        // shift by the padding so that voxel (x, y, z) spans [x, x + 1) in the chunk
+        self.positions.extend(
+            face.quad_mesh_positions(quad, voxel_size)
+                .into_iter()
+                .map(|[x, y, z]| [x - voxel_size, y - voxel_size, z - voxel_size]),
         );
+        self.normals.extend_from_slice(&face.quad_mesh_normals());
+        // one texture per voxel, repeated across the merged quad with the top of the texture up on the sides
+        self.uvs.extend_from_slice(&face.tex_coords(
+            RIGHT_HANDED_Y_UP_CONFIG.u_flip_face,
+            true,
+            quad,
+        ));
+        let layer = voxel.block().textures.face(face.signed_normal().to_array());
+        self.layers.extend([layer; 4]);
+        self.occlusion.extend(ao_light(ao));
+    }
 
-        let faces = RIGHT_HANDED_Y_UP_CONFIG.faces;
-        let num_indices = buffer.quads.num_quads() * 6;
-        let num_vertices = buffer.quads.num_quads() * 4;
-        let mut indices = Vec::with_capacity(num_indices);
-        let mut positions = Vec::with_capacity(num_vertices);
-        let mut normals = Vec::with_capacity(num_vertices);
-        let mut colors = Vec::with_capacity(num_vertices);
-
-        for (group, face) in buffer.quads.groups.into_iter().zip(faces.into_iter()) {
-            for quad in group.into_iter() {
-                // construct vectors for mesh
-                let face_indices = face.quad_mesh_indices(positions.len() as u32);
-                let face_positions = face.quad_mesh_positions(&quad, Self::voxel_size());
-                let face_colors: Vec<_> = face_positions
-                    .iter()
-                    .map(|_| {
-                        let i = ChunkShape::linearize(quad.minimum.map(|v| v).into());
-                        let voxel = voxels[i as usize];
-                        match voxel.value() {
-                            Some(v) => {
-                                let c = color(v);
-                                [c.r(), c.g(), c.b(), 1.0]
-                            }
-                            None => unreachable!(),
-                        }
-                    })
-                    .collect();
-
-                indices.extend_from_slice(&face_indices);
-                positions.extend_from_slice(&face_positions);
-                colors.extend_from_slice(&face_colors);
-                normals.extend_from_slice(&face.quad_mesh_normals());
-            }
+    fn build(self) -> Option<Mesh> {
+        if self.indices.is_empty() {
+            return None;
         }
 
         let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
 
         mesh.insert_attribute(
             Mesh::ATTRIBUTE_POSITION,
-            VertexAttributeValues::Float32x3(positions),
+            VertexAttributeValues::Float32x3(self.positions),
         );
         mesh.insert_attribute(
             Mesh::ATTRIBUTE_NORMAL,
-            VertexAttributeValues::Float32x3(normals),
+            VertexAttributeValues::Float32x3(self.normals),
         );
         mesh.insert_attribute(
             Mesh::ATTRIBUTE_UV_0,
-            VertexAttributeValues::Float32x2(vec![[0.0; 2]; num_vertices]),
+            VertexAttributeValues::Float32x2(self.uvs),
         );
         mesh.insert_attribute(
-            Mesh::ATTRIBUTE_COLOR,
-            VertexAttributeValues::Float32x4(colors),
+            ATTRIBUTE_TEXTURE_LAYER,
+            VertexAttributeValues::Uint32(self.layers),
         );
-        mesh.set_indices(Some(Indices::U32(indices.clone())));
+        mesh.insert_attribute(
+            ATTRIBUTE_OCCLUSION,
+            VertexAttributeValues::Float32(self.occlusion),
+        );
+        mesh.set_indices(Some(Indices::U32(self.indices)));
 
         Some(mesh)
     }
+}
 
-    fn voxel_coord(&self) -> (i64, i64, i64) {
-        (
-            self.x * CHUNK_VOXELS.x as i64,
-            self.y * CHUNK_VOXELS.y as i64,
-            self.z * CHUNK_VOXELS.z as i64,
-        )
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cave::CaveConfig, map::GenConfig, settings::WorldGenSettings};
+    use std::collections::HashMap;
+
+    /// Generator of a flat world without caves and water
+    fn flat_gen() -> TerrainGen {
+        let settings = WorldGenSettings::default();
+        let flat = |cfg: &GenConfig| GenConfig {
+            octaves: 0,
+            ..cfg.clone()
+        };
+
+        TerrainGen::new(&WorldGenSettings {
+            sea_level: -1000,
+            local_level: flat(&settings.local_level),
+            global_level: flat(&settings.global_level),
+            temperature: flat(&settings.temperature),
+            moisture: flat(&settings.moisture),
+            caves: CaveConfig {
+                density: 0.0,
+                overhang_amplitude: 0.0,
+                ..default()
+            },
+            ..settings
+        })
     }
-}
 
-fn color(level: u64) -> Color {
-    let c = if level < 22 {
-        let g = colorgrad::CustomGradient::new()
-            .colors(&[
-                colorgrad::Color::from_rgba8(0, 0, 30, 255),
-                colorgrad::Color::from_rgba8(30, 30, 200, 255),
-            ])
-            .build()
-            .unwrap();
-        g.at(level as f64 / 22.0)
-    } else if level >= 22 && level <= 24 {
-        let g = colorgrad::CustomGradient::new()
-            .colors(&[
-                colorgrad::Color::from_rgba8(195, 182, 153, 255),
-                colorgrad::Color::from_rgba8(190, 153, 72, 255),
-            ])
-            .build()
-            .unwrap();
-        g.at((level as f64 - 22.0) / 2.0)
-    } else if level > 24 && level <= 29 {
-        let g = colorgrad::CustomGradient::new()
-            .colors(&[
-                colorgrad::Color::from_rgba8(0, 114, 0, 255),
-                colorgrad::Color::from_rgba8(0, 20, 0, 255),
-            ])
-            .build()
-            .unwrap();
-        g.at((level as f64 - 25.0) / 4.0)
-    } else if level <= 50 {
-        let g = colorgrad::CustomGradient::new()
-            .colors(&[
-                colorgrad::Color::from_rgba8(207, 105, 17, 255),
-                colorgrad::Color::from_rgba8(105, 52, 5, 255),
-            ])
-            .build()
-            .unwrap();
-        g.at((level as f64 - 30.0) / 20.0)
-    } else {
-        let g = colorgrad::CustomGradient::new()
-            .colors(&[
-                colorgrad::Color::from_rgba8(69, 64, 59, 255),
-                colorgrad::Color::from_rgba8(30, 30, 30, 255),
-            ])
-            .build()
-            .unwrap();
-        g.at((level as f64 - 50.0) / 14.0)
-    };
-    Color::rgba(c.r as f32, c.g as f32, c.b as f32, 1.0)
-}
+    #[test]
+    fn flat_world_has_no_border_faces() {
+        let gen = flat_gen();
+        let (surface, _) = Chunk::from_voxel_coord(0, gen.column(0, 0).level, 0);
+
+        let mut quads = HashMap::new();
+        let mut top_area = 0.0;
+        for (x, y, z) in (-1..=1).flat_map(|x| {
+            (surface.y - 1..=surface.y + 1).flat_map(move |y| (-1..=1).map(move |z| (x, y, z)))
+        }) {
+            let chunk = Chunk::new(x, y, z);
+            let mesh = match mesh_voxels(&chunk.generate_voxels(&gen), 1.0, false).opaque {
+                Some(m) => m,
+                None => continue,
+            };
+
+            let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+                Some(VertexAttributeValues::Float32x3(p)) => p,
+                _ => unreachable!(),
+            };
+            let normals = match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+                Some(VertexAttributeValues::Float32x3(n)) => n,
+                _ => unreachable!(),
+            };
+
+            for (corners, normal) in positions.chunks(4).zip(normals.iter().step_by(4)) {
+                let normal = normal.map(|n| n as i32);
+                *quads.entry(normal).or_insert(0) += 1;
+
+                if normal == [0, 1, 0] {
+                    let [p0, p1, p2] = [0, 1, 2].map(|i| Vec3::from(corners[i]));
+                    top_area += p0.distance(p1) * p0.distance(p2);
+                }
+            }
+        }
 
-fn generate_voxels(x: i64, y: i64, z: i64) -> Voxel {
-    let g = crate::map::ProcGen::new(crate::map::local_level_cfg());
-    let local_level = g.gen(x, z);
-    let local_level = (local_level * 10.0) as i64;
-    let g = crate::map::ProcGen::new(crate::map::global_level_cfg());
-    let global_level = g.gen(x, z);
-    let global_level = (global_level * 10.0) as i64;
-    let level = if global_level <= 5 {
-        let v = global_level.saturating_sub(1);
-        v * v + local_level * 4 / 10
-    } else if global_level <= 8 {
-        20 + local_level
-    } else {
-        30 + local_level * local_level / 3
-    };
-
-    if y <= level {
-        Voxel::new(y as u64)
-    } else {
-        Voxel::EMPTY
+        // the top faces cover the 3 x 3 chunks once, and the borders between the chunks are culled
+        let columns = 9 * CHUNK_VOXELS.x * CHUNK_VOXELS.z;
+        assert_eq!(top_area, columns as f32);
+        assert_eq!(quads.keys().collect::<Vec<_>>(), vec![&[0, 1, 0]]);
+        assert!(quads[&[0, 1, 0]] >= 9, "{:?}", quads);
     }
 }
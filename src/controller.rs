@@ -0,0 +1,358 @@
+use crate::{
+    block::BlockId,
+    chunk::{voxel_at, Chunk},
+    edit::VoxelEdits,
+    generator::TerrainGen,
+};
+use bevy::prelude::*;
+use bevy_rapier3d::{
+    prelude::*,
+    rapier::{math::Isometry, parry::query::contact},
+};
+
+/// Radius and half length of the segment between the hemispheres of the capsule
+pub const CAPSULE_RADIUS: f32 = 0.35;
+pub const CAPSULE_HALF_HEIGHT: f32 = 0.5;
+
+/// Downward acceleration in m/s²
+const GRAVITY: f32 = 29.4;
+/// Height of a jump; clears a ledge of two voxels
+const JUMP_HEIGHT: f32 = 2.2;
+/// Rates the horizontal velocity approaches the walking velocity at
+const GROUND_ACCELERATION: f32 = 15.0;
+const AIR_ACCELERATION: f32 = 3.0;
+/// Rate the velocity approaches the swimming velocity at in liquid
+const SWIM_DRAG: f32 = 2.0;
+/// Vertical speeds while swimming up and sinking
+const SWIM_UP_SPEED: f32 = 4.0;
+const SINK_SPEED: f32 = 1.5;
+
+/// Steepest ground walkable as the minimum Y of its normal, about 50 degrees
+const MIN_GROUND_NORMAL_Y: f32 = 0.64;
+/// Highest ledge climbed without jumping; a voxel
+const STEP_HEIGHT: f32 = 1.05;
+/// Distance the ground is looked for below the body
+const GROUND_PROBE: f32 = 0.05;
+/// Distance the body is pulled down to stay on the ground walking down slopes
+const SNAP_DISTANCE: f32 = 0.3;
+/// Gap kept between the body and the terrain so that casts don't start in contact
+const SKIN: f32 = 0.02;
+/// Slides along the surfaces hit in one move, and pushes out of the terrain overlapped
+const MAX_SLIDES: usize = 4;
+
+/// Seconds a jump is still possible after walking off a ledge
+const COYOTE_TIME: f32 = 0.12;
+/// Seconds a jump pressed just before landing is kept
+const JUMP_BUFFER: f32 = 0.15;
+/// Longest frame simulated at once so that a hitch doesn't launch the body
+const MAX_DELTA: f32 = 0.1;
+
+/// Kinematic body moved by casting its capsule against the terrain
+#[derive(Component, Default)]
+pub struct CharacterController {
+    /// Horizontal velocity to walk at, set by the input every frame
+    pub walk: Vec3,
+    /// Swim up while set
+    pub swim_up: bool,
+    pub velocity: Vec3,
+    /// Seconds left to jump since the body was on the ground
+    coyote: f32,
+    /// Seconds left to jump since it was requested
+    jump_buffer: f32,
+}
+
+impl CharacterController {
+    /// Jump as soon as the body is on the ground
+    pub fn jump(&mut self) {
+        self.jump_buffer = JUMP_BUFFER;
+    }
+}
+
+/// Ground a character stands on; present only while it's on walkable ground
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Grounded {
+    /// Normal of the surface under the character
+    pub normal: Vec3,
+    /// Block the character stands on
+    pub block: BlockId,
+    /// Downward speed the character landed at in m/s; 0 when it walked onto the ground
+    pub impact_speed: f32,
+}
+
+/// Collider and body of a character; the transform is moved by `move_character_system`
+#[derive(Bundle)]
+pub struct CharacterBundle {
+    pub controller: CharacterController,
+    pub rigid_body: RigidBody,
+    pub collider: Collider,
+}
+
+impl Default for CharacterBundle {
+    fn default() -> Self {
+        Self {
+            controller: default(),
+            rigid_body: RigidBody::KinematicPositionBased,
+            collider: Collider::capsule_y(CAPSULE_HALF_HEIGHT, CAPSULE_RADIUS),
+        }
+    }
+}
+
+/// Casts of the capsule against the terrain
+struct Caster<'a> {
+    context: &'a RapierContext,
+    shape: Collider,
+}
+
+impl Caster<'_> {
+    /// Distance to the terrain along the unit direction within `max`, and the normal of the surface hit
+    fn cast(&self, pos: Vec3, dir: Vec3, max: f32) -> Option<(f32, Vec3)> {
+        let filter = QueryFilter::only_fixed();
+        let (_, hit) =
+            self.context
+                .cast_shape(pos, Quat::IDENTITY, dir, &self.shape, max + SKIN, filter)?;
+        // already touching; moving away is free while moving in is blocked right away
+        if hit.status == TOIStatus::Penetrating {
+            return (dir.dot(hit.normal1) < 0.0).then_some((0.0, hit.normal1));
+        }
+        // the first shape of the hit is the terrain, whose colliders aren't rotated
+        Some(((hit.toi - SKIN).max(0.0), hit.normal1))
+    }
+
+    /// Push the body out of the terrain it overlaps, e.g. after drifting into it or a voxel placed on it
+    fn depenetrate(&self, mut pos: Vec3) -> Vec3 {
+        for _ in 0..MAX_SLIDES {
+            let mut overlapped = vec![];
+            self.context.intersections_with_shape(
+                pos,
+                Quat::IDENTITY,
+                &self.shape,
+                QueryFilter::only_fixed(),
+                |entity| {
+                    overlapped.push(entity);
+                    true
+                },
+            );
+
+            let body: Isometry<Real> = (pos, Quat::IDENTITY).into();
+            let deepest = overlapped
+                .iter()
+                .filter_map(|entity| {
+                    let handle = self.context.entity2collider().get(entity)?;
+                    let terrain = self.context.colliders.get(*handle)?;
+                    // the first shape is the terrain so that the normal points out of it
+                    contact(
+                        terrain.position(),
+                        terrain.shape(),
+                        &body,
+                        &*self.shape.raw,
+                        0.0,
+                    )
+                    .ok()
+                    .flatten()
+                })
+                .min_by(|a, b| a.dist.total_cmp(&b.dist));
+
+            match deepest {
+                Some(c) if c.dist < 0.0 => pos += Vec3::from(*c.normal1) * (SKIN - c.dist),
+                _ => break,
+            }
+        }
+        pos
+    }
+
+    /// Move by the motion sliding along the surfaces hit, and return the normals of the surfaces
+    ///
+    /// With `walls`, the surfaces too steep to walk on are treated as vertical so that they can't be climbed.
+    fn slide(&self, mut pos: Vec3, mut motion: Vec3, walls: bool) -> (Vec3, Vec<Vec3>) {
+        let mut normals = vec![];
+        for _ in 0..MAX_SLIDES {
+            let length = motion.length();
+            if length < 1e-5 {
+                break;
+            }
+            let dir = motion / length;
+            let (distance, mut normal) = match self.cast(pos, dir, length) {
+                Some(hit) => hit,
+                None => {
+                    pos += motion;
+                    break;
+                }
+            };
+
+            pos += dir * distance;
+            normals.push(normal);
+            if walls && normal.y < MIN_GROUND_NORMAL_Y {
+                normal = Vec3::new(normal.x, 0.0, normal.z).normalize_or_zero();
+            }
+            motion = dir * (length - distance);
+            motion -= normal * motion.dot(normal).min(0.0);
+        }
+        (pos, normals)
+    }
+
+    /// Point of the body touching the ground under it at the position
+    fn lowest(&self, pos: Vec3, normal: Vec3) -> Vec3 {
+        let (half_height, radius) = self
+            .shape
+            .as_capsule()
+            .map_or((0.0, 0.0), |c| (c.half_height(), c.radius()));
+        pos - Vec3::Y * half_height - normal * radius
+    }
+
+    /// Climb the ledge in the way of the horizontal motion, and return the position on top of it
+    fn step_up(&self, pos: Vec3, motion: Vec3) -> Option<Vec3> {
+        let climb = self
+            .cast(pos, Vec3::Y, STEP_HEIGHT)
+            .map_or(STEP_HEIGHT, |(d, _)| d);
+        let (over, _) = self.slide(pos + Vec3::Y * climb, motion, true);
+        // stepping onto ground no higher than the climb; otherwise it's a wall or a drop
+        let (down, normal) = self.cast(over, -Vec3::Y, climb)?;
+        let moved = (over - pos) * Vec3::new(1.0, 0.0, 1.0);
+        (normal.y >= MIN_GROUND_NORMAL_Y && moved.length() > 1e-3).then(|| over - Vec3::Y * down)
+    }
+}
+
+/// The point is in liquid, e.g. water
+pub fn submerged(gen: &TerrainGen, edits: &VoxelEdits, pos: Vec3) -> bool {
+    let (x, y, z) = Chunk::voxel_coord_from_world(pos);
+    voxel_at(gen, edits, x, y, z).block().liquid
+}
+
+/// Walk, jump, fall and swim the characters
+///
+/// The velocity follows closed forms over the frame so that the motion is the same at any frame rate.
+pub fn move_character_system(
+    time: Res<Time>,
+    context: Res<RapierContext>,
+    gen: Res<TerrainGen>,
+    edits: Res<VoxelEdits>,
+    mut commands: Commands,
+    mut characters: Query<(
+        Entity,
+        &mut CharacterController,
+        &mut Transform,
+        &Collider,
+        Option<&mut Grounded>,
+    )>,
+) {
+    let dt = time.delta_seconds().min(MAX_DELTA);
+    if dt <= 0.0 {
+        return;
+    }
+
+    for (entity, mut controller, mut transform, collider, mut ground) in &mut characters {
+        let caster = Caster {
+            context: &context,
+            shape: collider.clone(),
+        };
+        let start = caster.depenetrate(transform.translation);
+        let mut pos = start;
+        let mut velocity = controller.velocity;
+        let swimming = submerged(&gen, &edits, pos);
+
+        controller.coyote = if ground.is_some() {
+            COYOTE_TIME
+        } else {
+            controller.coyote - dt
+        };
+        controller.jump_buffer -= dt;
+
+        // horizontal velocity eases toward the walking velocity
+        let (walk, rate) = if swimming {
+            (controller.walk * 0.5, SWIM_DRAG)
+        } else if ground.is_some() {
+            (controller.walk, GROUND_ACCELERATION)
+        } else {
+            (controller.walk, AIR_ACCELERATION)
+        };
+        let ease = |rate: f32| 1.0 - (-rate * dt).exp();
+        let horizontal = Vec3::new(velocity.x, 0.0, velocity.z).lerp(walk, ease(rate));
+
+        // vertical motion over the frame, exact under the constant gravity
+        let mut jumped = false;
+        let dy = if swimming {
+            let target = if controller.swim_up {
+                SWIM_UP_SPEED
+            } else {
+                -SINK_SPEED
+            };
+            velocity.y += (target - velocity.y) * ease(SWIM_DRAG);
+            velocity.y * dt
+        } else {
+            if controller.jump_buffer > 0.0 && controller.coyote > 0.0 {
+                velocity.y = (2.0 * GRAVITY * JUMP_HEIGHT).sqrt();
+                controller.jump_buffer = 0.0;
+                controller.coyote = 0.0;
+                jumped = true;
+            }
+            let dy = velocity.y * dt - 0.5 * GRAVITY * dt * dt;
+            velocity.y -= GRAVITY * dt;
+            dy
+        };
+
+        let motion = horizontal * dt;
+        let (walked, normals) = caster.slide(pos, motion, true);
+        let blocked = normals.iter().any(|n| n.y < MIN_GROUND_NORMAL_Y);
+        pos = if blocked && (ground.is_some() || swimming) && !jumped {
+            caster.step_up(pos, motion).unwrap_or(walked)
+        } else {
+            walked
+        };
+
+        let (fallen, normals) = caster.slide(pos, Vec3::Y * dy, false);
+        pos = fallen;
+        let impact_speed = (-velocity.y).max(0.0);
+        if !normals.is_empty() {
+            // stopped by the ground or the ceiling
+            velocity.y = if dy > 0.0 { velocity.y.min(0.0) } else { 0.0 };
+        }
+
+        // the walkable ground right below; farther on the ground so as to keep to it walking down slopes
+        let probe = if ground.is_some() && !jumped && !swimming {
+            SNAP_DISTANCE
+        } else {
+            GROUND_PROBE
+        };
+        let hit = if dy <= 0.0 {
+            caster.cast(pos, -Vec3::Y, probe)
+        } else {
+            None
+        };
+        match (
+            hit.filter(|(_, n)| n.y >= MIN_GROUND_NORMAL_Y),
+            ground.as_deref_mut(),
+        ) {
+            (Some((distance, normal)), ground) => {
+                pos.y -= distance;
+                velocity.y = 0.0;
+
+                // the voxel under the lowest point of the body
+                let contact = caster.lowest(pos, normal) - normal * Chunk::voxel_size() * 0.5;
+                let (x, y, z) = Chunk::voxel_coord_from_world(contact);
+                let block = voxel_at(&gen, &edits, x, y, z).id();
+                match ground {
+                    Some(ground) => {
+                        ground.normal = normal;
+                        ground.block = block;
+                    }
+                    None => {
+                        commands.entity(entity).insert(Grounded {
+                            normal,
+                            block,
+                            impact_speed,
+                        });
+                    }
+                }
+            }
+            (None, Some(_)) => {
+                commands.entity(entity).remove::<Grounded>();
+            }
+            (None, None) => {}
+        }
+
+        // keep the horizontal velocity actually moved so that it doesn't build up against walls
+        let moved = (pos - start) / dt;
+        controller.velocity = Vec3::new(moved.x, velocity.y, moved.z);
+        transform.translation = pos;
+    }
+}
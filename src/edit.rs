@@ -0,0 +1,269 @@
+use crate::{
+    block::BlockId,
+    chunk::{voxel_at, Chunk, CHUNK_VOXELS},
+    generator::TerrainGen,
+    input::{Action, ActionState},
+    player::Player,
+    voxel::Voxel,
+};
+use bevy::prelude::*;
+use bevy_rapier3d::prelude::*;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// Max distance to the voxel the player can reach
+const REACH: f32 = 16.0;
+
+/// Voxels modified in a chunk
+#[derive(Debug, Clone, Default)]
+pub struct ChunkEdits {
+    /// Incremented on every modification of the chunk or the neighbouring voxels in its padding
+    revision: u64,
+    voxels: HashMap<[u32; 3], Voxel>,
+}
+
+impl ChunkEdits {
+    /// Modified voxel at the coordinate local to the chunk
+    pub fn get(&self, x: u32, y: u32, z: u32) -> Option<Voxel> {
+        self.voxels.get(&[x, y, z]).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = ([u32; 3], Voxel)> + '_ {
+        self.voxels.iter().map(|(k, v)| (*k, *v))
+    }
+}
+
+/// Voxels modified in a chunk and its padding, in the coordinate of `ChunkShape`
+#[derive(Debug, Clone)]
+pub struct PaddedEdits {
+    revision: u64,
+    voxels: Vec<([u32; 3], Voxel)>,
+}
+
+impl PaddedEdits {
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = ([u32; 3], Voxel)> + '_ {
+        self.voxels.iter().copied()
+    }
+}
+
+/// Voxels modified by the player on top of the generated terrain
+#[derive(Debug, Clone, Default)]
+pub struct VoxelEdits(Arc<Mutex<HashMap<Chunk, ChunkEdits>>>);
+
+impl VoxelEdits {
+    /// Snapshot of the modifications in the chunk and the neighbouring voxels in its padding
+    pub fn padded(&self, chunk: &Chunk) -> Option<PaddedEdits> {
+        let edits = self.0.lock().unwrap();
+        let revision = edits.get(chunk)?.revision;
+
+        let size = [CHUNK_VOXELS.x, CHUNK_VOXELS.y, CHUNK_VOXELS.z].map(|s| s as i64);
+        let mut voxels = vec![];
+        for offset in neighbour_offsets([true; 3], [true; 3]) {
+            let neighbour = Chunk::new(
+                chunk.x + offset[0],
+                chunk.y + offset[1],
+                chunk.z + offset[2],
+            );
+            let neighbour = match edits.get(&neighbour) {
+                Some(e) => e,
+                None => continue,
+            };
+
+            for (local, voxel) in neighbour.iter() {
+                // shifted by the padding
+                let p = [0, 1, 2].map(|a| local[a] as i64 + offset[a] * size[a] + 1);
+                if (0..3).all(|a| 0 <= p[a] && p[a] <= size[a] + 1) {
+                    voxels.push((p.map(|v| v as u32), voxel));
+                }
+            }
+        }
+
+        Some(PaddedEdits { revision, voxels })
+    }
+
+    /// Snapshot of the modifications in all the chunks
+    pub fn snapshot(&self) -> Vec<(Chunk, ChunkEdits)> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            // chunks only re-meshed for the neighbours
+            .filter(|(_, e)| !e.voxels.is_empty())
+            .map(|(c, e)| (c.clone(), e.clone()))
+            .collect()
+    }
+
+    /// Chunks having any modifications in them or their padding
+    pub fn chunks(&self) -> Vec<Chunk> {
+        self.0.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Modified voxel at the voxel coordinate
+    pub fn voxel(&self, x: i64, y: i64, z: i64) -> Option<Voxel> {
+        let (chunk, [x, y, z]) = Chunk::from_voxel_coord(x, y, z);
+        self.0.lock().unwrap().get(&chunk)?.get(x, y, z)
+    }
+
+    pub fn revision(&self, chunk: &Chunk) -> u64 {
+        self.0
+            .lock()
+            .unwrap()
+            .get(chunk)
+            .map(|e| e.revision)
+            .unwrap_or(0)
+    }
+
+    /// Overwrite the voxel and return the chunks to be re-meshed
+    pub fn set(&self, x: i64, y: i64, z: i64, voxel: Voxel) -> Vec<Chunk> {
+        let (chunk, local) = Chunk::from_voxel_coord(x, y, z);
+
+        // neighbours have the voxel in their padding for the faces and the occlusion on the border
+        let size = [CHUNK_VOXELS.x, CHUNK_VOXELS.y, CHUNK_VOXELS.z];
+        let chunks: Vec<_> = neighbour_offsets(
+            [0, 1, 2].map(|a| local[a] == size[a] - 1),
+            [0, 1, 2].map(|a| local[a] == 0),
+        )
+        .map(|[dx, dy, dz]| Chunk::new(chunk.x + dx, chunk.y + dy, chunk.z + dz))
+        .collect();
+
+        let mut edits = self.0.lock().unwrap();
+        for c in &chunks {
+            edits.entry(c.clone()).or_default().revision += 1;
+        }
+        edits.get_mut(&chunk).unwrap().voxels.insert(local, voxel);
+
+        chunks
+    }
+}
+
+/// Offsets to the chunk itself and the neighbours in the directions along each axis, diagonals included
+fn neighbour_offsets(positive: [bool; 3], negative: [bool; 3]) -> impl Iterator<Item = [i64; 3]> {
+    let range = move |a: usize| -(negative[a] as i64)..=positive[a] as i64;
+    range(0).flat_map(move |x| range(1).flat_map(move |y| range(2).map(move |z| [x, y, z])))
+}
+
+/// Sent when the voxels of the chunk are modified
+pub struct ChunkModified(pub Chunk);
+
+/// Ray from the camera through the cursor, or the screen center if the cursor is grabbed or isn't available
+fn camera_ray(
+    windows: &Windows,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+    centered: bool,
+) -> Option<(Vec3, Vec3)> {
+    let size = camera.logical_viewport_size()?;
+    let cursor = windows
+        .get_primary()
+        .filter(|w| !centered && !w.cursor_locked())
+        .and_then(|w| w.cursor_position())
+        .unwrap_or(size / 2.0);
+
+    let ndc = cursor / size * 2.0 - Vec2::ONE;
+    let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix().inverse();
+    let near = ndc_to_world.project_point3(ndc.extend(1.0));
+    let far = ndc_to_world.project_point3(ndc.extend(0.5));
+
+    Some((near, (far - near).normalize()))
+}
+
+/// Voxel being dug and how long it's been dug
+#[derive(Default)]
+pub struct DigProgress {
+    target: Option<(i64, i64, i64)>,
+    elapsed: f32,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn edit_terrain_system(
+    time: Res<Time>,
+    windows: Res<Windows>,
+    actions: Res<ActionState>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    players: Query<(Entity, &Transform), With<Player>>,
+    context: Res<RapierContext>,
+    gen: Res<TerrainGen>,
+    edits: Res<VoxelEdits>,
+    mut progress: Local<DigProgress>,
+    mut modified: EventWriter<ChunkModified>,
+) {
+    let dig = actions.pressed(Action::Dig);
+    let place = actions.just_pressed(Action::Place);
+    if !dig {
+        *progress = DigProgress::default();
+    }
+    if !dig && !place {
+        return;
+    }
+
+    let (camera, camera_transform) = match cameras.iter().next() {
+        Some(c) => c,
+        None => return,
+    };
+    let (player, player_transform) = match players.iter().next() {
+        Some(p) => p,
+        None => return,
+    };
+    // the on-screen buttons aim at the crosshair as the pointer is on the button
+    let centered = actions.touched(if dig { Action::Dig } else { Action::Place });
+    let (origin, dir) = match camera_ray(&windows, camera, camera_transform, centered) {
+        Some(r) => r,
+        None => return,
+    };
+
+    // the camera stays behind the player; measure the reach from the player
+    let max_toi = origin.distance(player_transform.translation) + REACH;
+    let filter = QueryFilter::only_fixed().exclude_rigid_body(player);
+    let hit = match context.cast_ray_and_get_normal(origin, dir, max_toi, true, filter) {
+        Some((_, hit)) => hit,
+        None => return,
+    };
+
+    // step half a voxel into or out of the surface to pick the voxel
+    let offset = hit.normal * Chunk::voxel_size() * 0.5;
+    let chunks = if dig {
+        let (x, y, z) = Chunk::voxel_coord_from_world(hit.point - offset);
+        let block = voxel_at(&gen, &edits, x, y, z).block();
+        if !block.solid {
+            return;
+        }
+
+        if progress.target != Some((x, y, z)) {
+            progress.target = Some((x, y, z));
+            progress.elapsed = 0.0;
+        }
+        progress.elapsed += time.delta_seconds();
+        if progress.elapsed < block.hardness {
+            return;
+        }
+        *progress = DigProgress::default();
+
+        debug!("Dug {} at ({}, {}, {})", block.name, x, y, z);
+        edits.set(x, y, z, Voxel::EMPTY)
+    } else {
+        let (x, y, z) = Chunk::voxel_coord_from_world(hit.point + offset);
+        // don't bury the player or any other character
+        let half = Chunk::voxel_size() * 0.5;
+        let center = Vec3::new(x as f32, y as f32, z as f32) * Chunk::voxel_size() + half;
+        let voxel = Collider::cuboid(half, half, half);
+        let characters = QueryFilter::only_kinematic();
+        if context
+            .intersection_with_shape(center, Quat::IDENTITY, &voxel, characters)
+            .is_some()
+        {
+            return;
+        }
+        if voxel_at(&gen, &edits, x, y, z).block().solid {
+            return;
+        }
+        edits.set(x, y, z, Voxel::new(BlockId::DIRT))
+    };
+
+    modified.send_batch(chunks.into_iter().map(ChunkModified));
+}
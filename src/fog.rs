@@ -0,0 +1,153 @@
+use crate::{
+    chunk::{voxel_at, Chunk},
+    edit::VoxelEdits,
+    generator::TerrainGen,
+    input::{Action, ActionState},
+    light,
+    player::Player,
+    render::{TerrainFog, TerrainMaterial, TerrainRender},
+    terrain::Loader,
+};
+use bevy::prelude::*;
+
+/// Color and visible distance under water
+const UNDERWATER_COLOR: Color = Color::rgb(0.05, 0.2, 0.35);
+const UNDERWATER_DISTANCE: f32 = 24.0;
+
+/// Rate the visible distance approaches the one of the biome at, so that crossing biomes doesn't pop
+const FOG_EASE: f32 = 1.5;
+
+/// Fog density times the visible distance at which the exponential squared fog hides 99% of the terrain
+const EXP_VISIBLE: f32 = 2.146;
+
+/// Falloff of the fog by the distance
+#[derive(Debug, Clone, Copy)]
+pub enum FogFalloff {
+    /// Fades in from the fraction of the visible distance to the visible distance
+    Linear { start: f32 },
+    /// Exponential squared; thickens gradually from the camera
+    Exponential,
+}
+
+/// Fog hiding the terrain at the load distance of the player, or of the free-flying camera, in the color of the sky
+///
+/// The visible distance is scaled by the biome at the camera and shortened under water.
+pub struct FogSettings {
+    pub enabled: bool,
+    pub falloff: FogFalloff,
+}
+
+impl Default for FogSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            falloff: FogFalloff::Linear { start: 0.6 },
+        }
+    }
+}
+
+impl FogSettings {
+    fn fog(&self, color: Color, visible: f32) -> TerrainFog {
+        if !self.enabled {
+            return TerrainFog::default();
+        }
+
+        let color = Vec4::from(color.as_linear_rgba_f32());
+        match self.falloff {
+            FogFalloff::Linear { start } => TerrainFog {
+                color,
+                start: visible * start,
+                end: visible,
+                density: 0.0,
+            },
+            FogFalloff::Exponential => TerrainFog {
+                color,
+                density: EXP_VISIBLE / visible,
+                ..default()
+            },
+        }
+    }
+}
+
+pub struct FogPlugin;
+
+impl Plugin for FogPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FogSettings>()
+            .add_system(toggle_fog_system)
+            .add_system(
+                update_fog_system
+                    .after(toggle_fog_system)
+                    .after(light::update_sky_system),
+            );
+    }
+}
+
+/// Cycle through the linear, the exponential and no fog
+fn toggle_fog_system(actions: Res<ActionState>, mut settings: ResMut<FogSettings>) {
+    if !actions.just_pressed(Action::ToggleFog) {
+        return;
+    }
+
+    let default = FogSettings::default();
+    *settings = match (settings.enabled, settings.falloff) {
+        (false, _) => default,
+        (true, FogFalloff::Linear { .. }) => FogSettings {
+            falloff: FogFalloff::Exponential,
+            ..default
+        },
+        (true, FogFalloff::Exponential) => FogSettings {
+            enabled: false,
+            ..default
+        },
+    };
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_fog_system(
+    time: Res<Time>,
+    settings: Res<FogSettings>,
+    gen: Res<TerrainGen>,
+    edits: Res<VoxelEdits>,
+    render: Res<TerrainRender>,
+    mut clear_color: ResMut<ClearColor>,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
+    cameras: Query<(&GlobalTransform, Option<&Loader>), With<Camera3d>>,
+    players: Query<&Loader, With<Player>>,
+    mut biome_distance: Local<Option<f32>>,
+) {
+    // the terrain is loaded around the free-flying camera rather than the player
+    let (camera, loader) = match cameras.iter().next() {
+        Some((camera, loader)) => (camera.translation(), loader.or(players.iter().next())),
+        None => return,
+    };
+    let loader = match loader {
+        Some(l) => l,
+        None => return,
+    };
+    let (x, y, z) = Chunk::voxel_coord_from_world(camera);
+
+    let target = loader.shape().radius * gen.column(x, z).biome.fog();
+    let eased = match *biome_distance {
+        Some(distance) => {
+            distance + (target - distance) * (1.0 - (-FOG_EASE * time.delta_seconds()).exp())
+        }
+        None => target,
+    };
+    *biome_distance = Some(eased);
+
+    let (color, visible) = if voxel_at(&gen, &edits, x, y, z).block().liquid {
+        // the sky is hidden by the water as well
+        clear_color.0 = UNDERWATER_COLOR;
+        (UNDERWATER_COLOR, UNDERWATER_DISTANCE)
+    } else {
+        (clear_color.0, eased)
+    };
+
+    let fog = settings.fog(color, visible);
+    for handle in [&render.opaque, &render.translucent] {
+        if matches!(materials.get(handle), Some(material) if material.fog != fog) {
+            materials.get_mut(handle).unwrap().fog = fog;
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use crate::{
+    biome::level_bounds,
+    block::BlockId,
+    cave::CaveGen,
+    chunk::Chunk,
+    heightmap::{Column, HeightMap, HeightMapCache},
+    settings::WorldGenSettings,
+    voxel::Voxel,
+};
+use std::sync::Arc;
+
+/// Depth of the subsurface layer below the surface voxel
+const SUBSURFACE_DEPTH: f32 = 3.0;
+
+/// What the generated voxels of a chunk look like
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Occupancy {
+    /// Every voxel is empty
+    Empty,
+    /// Every voxel is solid and no caves can be carved
+    Full,
+    /// The chunk may have surfaces
+    Mixed,
+}
+
+/// Generates the voxels of the world; shared by the chunk generation tasks
+#[derive(Clone)]
+pub struct TerrainGen {
+    heightmaps: HeightMapCache,
+    caves: Arc<CaveGen>,
+    sea_level: i64,
+}
+
+impl TerrainGen {
+    pub fn new(settings: &WorldGenSettings) -> Self {
+        Self {
+            heightmaps: HeightMapCache::new(settings.level_gen()),
+            caves: Arc::new(settings.cave_gen()),
+            sea_level: settings.sea_level,
+        }
+    }
+
+    /// Height map of the column containing the chunk
+    pub fn heightmap(&self, chunk: &Chunk) -> Arc<HeightMap> {
+        self.heightmaps.get(chunk)
+    }
+
+    /// Drop the cached data of the columns no longer needed
+    pub fn retain(&self, f: impl FnMut(i64, i64) -> bool) {
+        self.heightmaps.retain(f);
+    }
+
+    /// Conservative occupancy of the voxels between the levels from the level bounds of the biomes;
+    /// cheap enough to call for every chunk in range
+    pub fn occupancy(&self, range_y: (i64, i64)) -> Occupancy {
+        self.occupancy_in(range_y, level_bounds())
+    }
+
+    /// Occupancy of the voxels between the levels in the column of chunks from the level bounds of its height map
+    pub fn column_occupancy(&self, range_y: (i64, i64), heightmap: &HeightMap) -> Occupancy {
+        self.occupancy_in(range_y, heightmap.bounds())
+    }
+
+    fn occupancy_in(
+        &self,
+        (min_y, max_y): (i64, i64),
+        (min_level, max_level): (i64, i64),
+    ) -> Occupancy {
+        let margin = self.caves.overhang_amplitude().ceil() as i64;
+        // water fills up to the sea level
+        let top = (max_level + margin).max(self.sea_level - 1);
+
+        if min_y > top {
+            Occupancy::Empty
+        } else if max_y < min_level - margin && !self.caves.carves(min_y, max_y) {
+            Occupancy::Full
+        } else {
+            Occupancy::Mixed
+        }
+    }
+
+    /// Terrain level and biome at the voxel coordinate without caching
+    pub fn column(&self, x: i64, z: i64) -> Column {
+        self.heightmaps.level_gen().column(x, z)
+    }
+
+    /// Voxel at the voxel coordinate in the column
+    pub fn voxel(&self, x: i64, y: i64, z: i64, column: Column) -> Voxel {
+        let depth = self.caves.depth(x, y, z, column.level);
+        if depth < 0.0 {
+            return if y < self.sea_level {
+                Voxel::new(BlockId::WATER)
+            } else {
+                Voxel::EMPTY
+            };
+        }
+        if self.caves.is_carved(x, y, z) {
+            return Voxel::EMPTY;
+        }
+
+        let id = if depth < 1.0 {
+            column.biome.surface(column.level)
+        } else if depth < SUBSURFACE_DEPTH {
+            column.biome.subsurface()
+        } else {
+            BlockId::STONE
+        };
+
+        Voxel::new(id)
+    }
+}
@@ -0,0 +1,141 @@
+use crate::{
+    biome::{Biome, Climate},
+    chunk::{Chunk, CHUNK_VOXELS},
+    map::{GenConfig, ProcGen},
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// Computes terrain levels and biomes from the procedural noise maps
+pub struct LevelGen {
+    local: ProcGen,
+    global: ProcGen,
+    temperature: ProcGen,
+    moisture: ProcGen,
+}
+
+impl LevelGen {
+    pub fn new(
+        local: GenConfig,
+        global: GenConfig,
+        temperature: GenConfig,
+        moisture: GenConfig,
+    ) -> Self {
+        Self {
+            local: ProcGen::new(local),
+            global: ProcGen::new(global),
+            temperature: ProcGen::new(temperature),
+            moisture: ProcGen::new(moisture),
+        }
+    }
+
+    /// Terrain level and biome at the voxel coordinate
+    pub fn column(&self, x: i64, z: i64) -> Column {
+        let climate = Climate {
+            continentalness: self.global.gen(x, z),
+            temperature: self.temperature.gen(x, z),
+            moisture: self.moisture.gen(x, z),
+        };
+        let level = climate.level(self.local.gen(x, z));
+        let biome = climate.biome(dither(x, z));
+
+        Column { level, biome }
+    }
+}
+
+/// Deterministic value in [0, 1) per column
+fn dither(x: i64, z: i64) -> f32 {
+    let mut h = (x as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (z as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f);
+    h ^= h >> 29;
+    h = h.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h ^= h >> 32;
+    (h >> 40) as f32 / (1u64 << 24) as f32
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Column {
+    pub level: i64,
+    pub biome: Biome,
+}
+
+/// Terrain levels and biomes of a column of vertically stacked chunks
+pub struct HeightMap {
+    columns: Vec<Column>,
+    bounds: (i64, i64),
+}
+
+impl HeightMap {
+    fn generate(gen: &LevelGen, column: (i64, i64)) -> Self {
+        let base_x = column.0 * CHUNK_VOXELS.x as i64;
+        let base_z = column.1 * CHUNK_VOXELS.z as i64;
+
+        let columns: Vec<_> = (0..CHUNK_VOXELS.z as i64)
+            .flat_map(|z| (0..CHUNK_VOXELS.x as i64).map(move |x| (x, z)))
+            .map(|(x, z)| gen.column(base_x + x, base_z + z))
+            .collect();
+        let bounds = columns.iter().fold((i64::MAX, i64::MIN), |(lo, hi), c| {
+            (lo.min(c.level), hi.max(c.level))
+        });
+
+        Self { columns, bounds }
+    }
+
+    /// Min and max terrain levels in the column
+    pub fn bounds(&self) -> (i64, i64) {
+        self.bounds
+    }
+
+    /// Terrain level and biome at the voxel coordinate local to the chunk column
+    pub fn column(&self, x: u32, z: u32) -> Column {
+        self.columns[(z * CHUNK_VOXELS.x + x) as usize]
+    }
+}
+
+type HeightMaps = HashMap<(i64, i64), Arc<HeightMap>>;
+
+/// Height maps shared by the chunk generation tasks
+#[derive(Clone)]
+pub struct HeightMapCache {
+    gen: Arc<LevelGen>,
+    maps: Arc<Mutex<HeightMaps>>,
+}
+
+impl HeightMapCache {
+    pub fn new(gen: LevelGen) -> Self {
+        Self {
+            gen: Arc::new(gen),
+            maps: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn level_gen(&self) -> &LevelGen {
+        &self.gen
+    }
+
+    /// Get the height map of the column containing the chunk, generating it on the first access
+    pub fn get(&self, chunk: &Chunk) -> Arc<HeightMap> {
+        let column = (chunk.x, chunk.z);
+
+        if let Some(map) = self.maps.lock().unwrap().get(&column) {
+            return map.clone();
+        }
+
+        // generate without holding the lock so that other columns aren't blocked
+        let map = Arc::new(HeightMap::generate(&self.gen, column));
+
+        self.maps
+            .lock()
+            .unwrap()
+            .entry(column)
+            .or_insert(map)
+            .clone()
+    }
+
+    /// Drop the height maps of the columns no longer needed
+    pub fn retain(&self, mut f: impl FnMut(i64, i64) -> bool) {
+        self.maps.lock().unwrap().retain(|(x, z), _| f(*x, *z));
+    }
+}
@@ -0,0 +1,475 @@
+use bevy::{input::InputSystem, prelude::*, ui::FocusPolicy};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// Bindings file read at startup on native
+#[cfg(not(target_arch = "wasm32"))]
+const BINDINGS_PATH: &str = "bindings.ron";
+
+/// Size of the on-screen buttons and the gap between them in logical pixels
+const BUTTON_SIZE: f32 = 64.0;
+const BUTTON_GAP: f32 = 8.0;
+/// Size of the dot at the center of the screen that the buttons dig and place at
+const CROSSHAIR_SIZE: f32 = 6.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    MoveForward,
+    MoveBack,
+    MoveLeft,
+    MoveRight,
+    Jump,
+    Sprint,
+    Dig,
+    Place,
+    /// Rotate the orbit camera by the mouse while held
+    Orbit,
+    FlyUp,
+    FlyDown,
+    LookLeft,
+    LookRight,
+    LookUp,
+    LookDown,
+    SwitchCamera,
+    GrabCursor,
+    SkipHour,
+    ToggleFog,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButtonType),
+}
+
+/// Bindings of the actions to the inputs
+///
+/// Read from `bindings.ron` on native, e.g. `(actions: {Jump: [Key(Space), Gamepad(South)]}, dead_zone: 0.2)`;
+/// the actions missing in the file keep the default bindings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputBindings {
+    pub actions: HashMap<Action, Vec<Binding>>,
+    /// Deflection of the gamepad sticks ignored around the center
+    pub dead_zone: f32,
+    /// Radians per second the camera turns by the look actions or the right stick
+    pub look_speed: f32,
+}
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        use Action::*;
+        use Binding::*;
+        use GamepadButtonType as Pad;
+
+        let actions = [
+            (MoveForward, vec![Key(KeyCode::W)]),
+            (MoveBack, vec![Key(KeyCode::S)]),
+            (MoveLeft, vec![Key(KeyCode::A)]),
+            (MoveRight, vec![Key(KeyCode::D)]),
+            (Jump, vec![Key(KeyCode::J), Gamepad(Pad::South)]),
+            (Sprint, vec![Key(KeyCode::K), Gamepad(Pad::LeftThumb)]),
+            (
+                Dig,
+                vec![Mouse(MouseButton::Left), Gamepad(Pad::RightTrigger2)],
+            ),
+            (
+                Place,
+                vec![Mouse(MouseButton::Right), Gamepad(Pad::LeftTrigger2)],
+            ),
+            (Orbit, vec![Mouse(MouseButton::Middle)]),
+            (FlyUp, vec![Key(KeyCode::Space), Gamepad(Pad::RightTrigger)]),
+            (
+                FlyDown,
+                vec![Key(KeyCode::LShift), Gamepad(Pad::LeftTrigger)],
+            ),
+            (LookLeft, vec![Key(KeyCode::Left)]),
+            (LookRight, vec![Key(KeyCode::Right)]),
+            (LookUp, vec![Key(KeyCode::Up)]),
+            (LookDown, vec![Key(KeyCode::Down)]),
+            (SwitchCamera, vec![Key(KeyCode::C), Gamepad(Pad::North)]),
+            (GrabCursor, vec![Key(KeyCode::Escape)]),
+            (SkipHour, vec![Key(KeyCode::T), Gamepad(Pad::DPadRight)]),
+            (ToggleFog, vec![Key(KeyCode::F), Gamepad(Pad::DPadUp)]),
+        ];
+
+        Self {
+            actions: actions.into_iter().collect(),
+            dead_zone: 0.15,
+            look_speed: 2.5,
+        }
+    }
+}
+
+impl InputBindings {
+    /// Load the bindings file over the default bindings
+    #[cfg(not(target_arch = "wasm32"))]
+    fn load() -> Self {
+        let mut bindings = Self::default();
+        let data = match std::fs::read_to_string(BINDINGS_PATH) {
+            Ok(data) => data,
+            Err(_) => return bindings,
+        };
+
+        match ron::from_str::<Self>(&data) {
+            Ok(file) => {
+                bindings.actions.extend(file.actions);
+                bindings.dead_zone = file.dead_zone;
+                bindings.look_speed = file.look_speed;
+            }
+            Err(e) => warn!("Ignoring the broken bindings `{}`: {}", BINDINGS_PATH, e),
+        }
+        bindings
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn load() -> Self {
+        Self::default()
+    }
+}
+
+/// State of the actions in the current frame, from every bound input and the on-screen controls
+#[derive(Default)]
+pub struct ActionState {
+    pressed: HashSet<Action>,
+    just_pressed: HashSet<Action>,
+    /// Pressed by the on-screen buttons
+    touched: HashSet<Action>,
+    /// Walking direction with x to the right and y backward, no longer than 1
+    pub movement: Vec2,
+    /// Turn of the camera in radians per second with x to the right and y up
+    pub look: Vec2,
+}
+
+impl ActionState {
+    pub fn pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+
+    /// Pressed by an on-screen button rather than a bound input
+    pub fn touched(&self, action: Action) -> bool {
+        self.touched.contains(&action)
+    }
+
+    /// 1 if only `pos` is pressed, -1 if only `neg` is, else 0
+    pub fn axis(&self, pos: Action, neg: Action) -> f32 {
+        let value = |action| if self.pressed(action) { 1.0 } else { 0.0 };
+        value(pos) - value(neg)
+    }
+}
+
+/// On-screen button pressing the action
+#[derive(Component)]
+pub struct TouchControl(pub Action);
+
+/// Maps the inputs to `ActionState`
+pub struct InputPlugin {
+    /// Show the on-screen buttons; on by default on the browser so that it's playable on phones
+    pub touch_controls: bool,
+}
+
+// not derivable on the browser
+#[allow(clippy::derivable_impls)]
+impl Default for InputPlugin {
+    fn default() -> Self {
+        Self {
+            touch_controls: cfg!(target_arch = "wasm32"),
+        }
+    }
+}
+
+impl Plugin for InputPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(InputBindings::load())
+            .init_resource::<ActionState>()
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                update_actions_system.after(InputSystem),
+            );
+
+        if self.touch_controls {
+            app.add_startup_system(create_touch_controls_system)
+                .add_system(highlight_touch_controls_system);
+
+            #[cfg(target_arch = "wasm32")]
+            app.init_resource::<web::TouchQueue>()
+                .add_startup_system(web::listen_touches_system)
+                .add_system_to_stage(
+                    CoreStage::PreUpdate,
+                    web::forward_touches_system.before(InputSystem),
+                );
+        }
+    }
+}
+
+/// The point in the window, from the bottom left, is on the UI node
+fn hit(node: &Node, transform: &GlobalTransform, point: Vec2) -> bool {
+    let offset = (point - transform.translation().truncate()).abs();
+    offset.x <= node.size.x / 2.0 && offset.y <= node.size.y / 2.0
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_actions_system(
+    bindings: Res<InputBindings>,
+    keys: Res<Input<KeyCode>>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    windows: Res<Windows>,
+    touches: Res<Touches>,
+    touch_controls: Query<(&TouchControl, &Node, &GlobalTransform)>,
+    mut state: ResMut<ActionState>,
+) {
+    let window = windows.get_primary();
+    let cursor = window.and_then(|w| w.cursor_position());
+    let height = window.map_or(0.0, |w| w.height());
+
+    // every finger presses the button under it; bevy 0.8 flips the touches to start from the bottom only on mobile
+    let flipped = cfg!(any(target_os = "android", target_os = "ios"));
+    let mut pointers: Vec<_> = touches
+        .iter()
+        .map(|t| t.position())
+        .map(|p| {
+            if flipped {
+                p
+            } else {
+                Vec2::new(p.x, height - p.y)
+            }
+        })
+        .collect();
+    // the mouse presses the one under the cursor instead of acting in the world
+    let over_controls = cursor.is_some_and(|c| touch_controls.iter().any(|(_, n, t)| hit(n, t, c)));
+    if mouse_buttons.pressed(MouseButton::Left) {
+        pointers.extend(cursor);
+    }
+    let touched: HashSet<_> = touch_controls
+        .iter()
+        .filter(|(_, node, transform)| pointers.iter().any(|p| hit(node, transform, *p)))
+        .map(|(control, _, _)| control.0)
+        .collect();
+
+    let bound = |binding: &Binding| match *binding {
+        Binding::Key(key) => keys.pressed(key),
+        Binding::Mouse(button) => !over_controls && mouse_buttons.pressed(button),
+        Binding::Gamepad(button) => gamepads
+            .iter()
+            .any(|&g| gamepad_buttons.pressed(GamepadButton::new(g, button))),
+    };
+    let mut pressed: HashSet<_> = bindings
+        .actions
+        .iter()
+        .filter(|(_, bindings)| bindings.iter().any(bound))
+        .map(|(&action, _)| action)
+        .collect();
+    pressed.extend(touched.iter().copied());
+
+    state.just_pressed = pressed.difference(&state.pressed).copied().collect();
+    state.pressed = pressed;
+    state.touched = touched;
+
+    let stick = |x, y| {
+        gamepads
+            .iter()
+            .map(|&g| {
+                let axis = |axis_type| {
+                    gamepad_axes
+                        .get(GamepadAxis::new(g, axis_type))
+                        .unwrap_or_default()
+                };
+                Vec2::new(axis(x), axis(y))
+            })
+            .find(|v| v.length() > bindings.dead_zone)
+            .unwrap_or_default()
+    };
+
+    // the stick points up for forward
+    let left =
+        stick(GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY) * Vec2::new(1.0, -1.0);
+    let keys = Vec2::new(
+        state.axis(Action::MoveRight, Action::MoveLeft),
+        state.axis(Action::MoveBack, Action::MoveForward),
+    );
+    state.movement = (keys + left).clamp_length_max(1.0);
+
+    let right = stick(GamepadAxisType::RightStickX, GamepadAxisType::RightStickY);
+    let keys = Vec2::new(
+        state.axis(Action::LookRight, Action::LookLeft),
+        state.axis(Action::LookUp, Action::LookDown),
+    );
+    state.look = (keys + right).clamp_length_max(1.0) * bindings.look_speed;
+}
+
+/// Buttons for moving at the bottom left and for the other actions at the bottom right, and the crosshair
+fn create_touch_controls_system(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            color: Color::NONE.into(),
+            focus_policy: FocusPolicy::Pass,
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn_bundle(NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Px(CROSSHAIR_SIZE), Val::Px(CROSSHAIR_SIZE)),
+                    ..default()
+                },
+                color: Color::rgba(1.0, 1.0, 1.0, 0.8).into(),
+                ..default()
+            });
+        });
+
+    let step = BUTTON_SIZE + BUTTON_GAP;
+    // icon and the column and row counted from the corner
+    let left = [
+        (Action::MoveForward, "forward", 1.0, 2.0),
+        (Action::MoveLeft, "left", 0.0, 1.0),
+        (Action::MoveRight, "right", 2.0, 1.0),
+        (Action::MoveBack, "back", 1.0, 0.0),
+    ];
+    let right = [
+        (Action::Jump, "jump", 0.0, 0.0),
+        (Action::Sprint, "sprint", 1.0, 0.0),
+        (Action::Dig, "dig", 0.0, 1.0),
+        (Action::Place, "place", 1.0, 1.0),
+        (Action::LookRight, "look_right", 0.0, 2.0),
+        (Action::SwitchCamera, "camera", 1.0, 2.0),
+        (Action::LookLeft, "look_left", 2.0, 2.0),
+    ];
+
+    let mut button = |action, icon: &str, position| {
+        commands
+            .spawn_bundle(ButtonBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position,
+                    size: Size::new(Val::Px(BUTTON_SIZE), Val::Px(BUTTON_SIZE)),
+                    ..default()
+                },
+                image: asset_server
+                    .load(&format!("textures/touch/{}.png", icon))
+                    .into(),
+                ..default()
+            })
+            .insert(TouchControl(action));
+    };
+
+    for (action, icon, column, row) in left {
+        button(
+            action,
+            icon,
+            UiRect {
+                left: Val::Px(BUTTON_GAP + column * step),
+                bottom: Val::Px(BUTTON_GAP + row * step),
+                ..default()
+            },
+        );
+    }
+    for (action, icon, column, row) in right {
+        button(
+            action,
+            icon,
+            UiRect {
+                right: Val::Px(BUTTON_GAP + column * step),
+                bottom: Val::Px(BUTTON_GAP + row * step),
+                ..default()
+            },
+        );
+    }
+}
+
+fn highlight_touch_controls_system(
+    state: Res<ActionState>,
+    mut controls: Query<(&TouchControl, &mut UiColor)>,
+) {
+    for (control, mut color) in &mut controls {
+        let target = if state.touched(control.0) {
+            Color::rgb(1.0, 0.8, 0.4)
+        } else {
+            Color::WHITE
+        };
+        // only when changed so that the UI isn't rebuilt every frame
+        if color.0 != target {
+            color.0 = target;
+        }
+    }
+}
+
+/// Touches on the canvas forwarded to `Touches`, as winit 0.26 turns the fingers into a single mouse on the browser
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use bevy::{
+        input::touch::{TouchInput, TouchPhase},
+        prelude::*,
+    };
+    use std::sync::{Arc, Mutex};
+    use wasm_bindgen::{closure::Closure, JsCast};
+
+    /// Touches received from the page since the last frame
+    #[derive(Default)]
+    pub struct TouchQueue(Arc<Mutex<Vec<TouchInput>>>);
+
+    pub fn listen_touches_system(queue: Res<TouchQueue>) {
+        let canvas = match web_sys::window()
+            .and_then(|w| w.document())
+            .and_then(|d| d.query_selector("canvas").ok().flatten())
+        {
+            Some(c) => c,
+            None => return,
+        };
+
+        for (name, phase) in [
+            ("touchstart", TouchPhase::Started),
+            ("touchmove", TouchPhase::Moved),
+            ("touchend", TouchPhase::Ended),
+            ("touchcancel", TouchPhase::Cancelled),
+        ] {
+            let queue = queue.0.clone();
+            let target = canvas.clone();
+            let listener = Closure::<dyn FnMut(web_sys::TouchEvent)>::wrap(Box::new(
+                move |event: web_sys::TouchEvent| {
+                    // keep the browser from sending the touches as the mouse as well
+                    event.prevent_default();
+
+                    // from the top left of the canvas like the touches of winit
+                    let rect = target.get_bounding_client_rect();
+                    let touches = event.changed_touches();
+                    let mut queue = queue.lock().unwrap();
+                    for touch in (0..touches.length()).filter_map(|i| touches.get(i)) {
+                        queue.push(TouchInput {
+                            phase,
+                            position: Vec2::new(
+                                (touch.client_x() as f64 - rect.left()) as f32,
+                                (touch.client_y() as f64 - rect.top()) as f32,
+                            ),
+                            force: None,
+                            id: touch.identifier() as u64,
+                        });
+                    }
+                },
+            ));
+            let _ =
+                canvas.add_event_listener_with_callback(name, listener.as_ref().unchecked_ref());
+            // listens as long as the page
+            listener.forget();
+        }
+    }
+
+    pub fn forward_touches_system(queue: Res<TouchQueue>, mut events: EventWriter<TouchInput>) {
+        for touch in queue.0.lock().unwrap().drain(..) {
+            events.send(touch);
+        }
+    }
+}
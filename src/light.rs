@@ -1,14 +1,248 @@
-use bevy::prelude::*;
+use crate::{
+    input::{Action, ActionState},
+    player::Player,
+};
+use bevy::{pbr::DirectionalLightShadowMap, prelude::*};
+use std::f32::consts::TAU;
 
-pub fn create_light(commands: &mut Commands) {
-    commands.spawn_bundle(PointLightBundle {
-        point_light: PointLight {
-            intensity: 50000.0,
-            range: 500.,
-            shadows_enabled: true,
+/// Seconds of a full day
+const DAY_LENGTH: f32 = 600.0;
+
+/// Illuminance of the sun and the moon at the zenith in lux
+const SUN_ILLUMINANCE: f32 = 30000.0;
+const MOON_ILLUMINANCE: f32 = 1500.0;
+
+/// Half extent of the shadow map around the player
+///
+/// Bevy 0.8 has no cascaded shadow maps and draws a single directional light, so one map covers the whole range
+/// instead of cascades; nearby shadows get the same texel size as the far ones, about 9 cm at the default 2048
+/// texels, and nothing casts shadows beyond the extent.
+const SHADOW_SIZE: f32 = 96.0;
+
+/// Sky at a time of day
+struct SkyKey {
+    time: f32,
+    clear: Color,
+    ambient: Color,
+    brightness: f32,
+}
+
+/// Sky through night, dawn, day and dusk, interpolated between the keys
+const SKY: [SkyKey; 6] = [
+    SkyKey {
+        time: 0.21,
+        clear: Color::rgb(0.01, 0.01, 0.04),
+        ambient: Color::rgb(0.4, 0.45, 0.8),
+        brightness: 0.05,
+    },
+    SkyKey {
+        time: 0.27,
+        clear: Color::rgb(0.8, 0.45, 0.3),
+        ambient: Color::rgb(1.0, 0.75, 0.6),
+        brightness: 0.2,
+    },
+    SkyKey {
+        time: 0.33,
+        clear: Color::rgb(0.45, 0.7, 1.0),
+        ambient: Color::WHITE,
+        brightness: 0.4,
+    },
+    SkyKey {
+        time: 0.67,
+        clear: Color::rgb(0.45, 0.7, 1.0),
+        ambient: Color::WHITE,
+        brightness: 0.4,
+    },
+    SkyKey {
+        time: 0.73,
+        clear: Color::rgb(0.85, 0.4, 0.25),
+        ambient: Color::rgb(1.0, 0.7, 0.55),
+        brightness: 0.2,
+    },
+    SkyKey {
+        time: 0.79,
+        clear: Color::rgb(0.01, 0.01, 0.04),
+        ambient: Color::rgb(0.4, 0.45, 0.8),
+        brightness: 0.05,
+    },
+];
+
+fn lerp_color(a: Color, b: Color, t: f32) -> Color {
+    let (a, b) = (Vec4::from(a.as_rgba_f32()), Vec4::from(b.as_rgba_f32()));
+    Color::from(a.lerp(b, t))
+}
+
+/// Time of day; set `time` to jump to a time
+pub struct TimeOfDay {
+    /// Fraction of the day in [0, 1); 0 is midnight, 0.25 sunrise, 0.5 noon and 0.75 sunset
+    pub time: f32,
+    /// Seconds of a full day
+    pub day_length: f32,
+    pub paused: bool,
+}
+
+impl TimeOfDay {
+    /// Hours in [0, 24)
+    pub fn hours(&self) -> f32 {
+        self.time * 24.0
+    }
+
+    pub fn set_hours(&mut self, hours: f32) {
+        self.time = (hours / 24.0).rem_euclid(1.0);
+    }
+
+    /// Unit vector toward the sun; rises in +X and sets in -X, tilted toward -Z
+    pub fn sun_direction(&self) -> Vec3 {
+        let angle = (self.time - 0.25) * TAU;
+        Vec3::new(angle.cos(), angle.sin(), -0.3 * angle.sin()).normalize()
+    }
+
+    /// Clear color, ambient color and ambient brightness of the sky
+    fn sky(&self) -> (Color, Color, f32) {
+        // wraps around midnight
+        let next = SKY.iter().position(|k| k.time > self.time).unwrap_or(0);
+        let (a, b) = (&SKY[(next + SKY.len() - 1) % SKY.len()], &SKY[next]);
+        let span = (b.time - a.time).rem_euclid(1.0);
+        let t = (self.time - a.time).rem_euclid(1.0) / span;
+
+        (
+            lerp_color(a.clear, b.clear, t),
+            lerp_color(a.ambient, b.ambient, t),
+            a.brightness + (b.brightness - a.brightness) * t,
+        )
+    }
+}
+
+#[derive(Component)]
+pub struct Sun;
+
+#[derive(Component)]
+pub struct Moon;
+
+/// Day/night cycle with the sun and the moon lighting the terrain
+pub struct DayNightPlugin {
+    /// Seconds of a full day
+    pub day_length: f32,
+    /// Time of day to start at
+    pub start: f32,
+}
+
+impl Default for DayNightPlugin {
+    fn default() -> Self {
+        Self {
+            day_length: DAY_LENGTH,
+            start: 0.3,
+        }
+    }
+}
+
+impl Plugin for DayNightPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(TimeOfDay {
+            time: self.start,
+            day_length: self.day_length,
+            paused: false,
+        })
+        .add_startup_system(create_lights_system)
+        .add_system(advance_time_system)
+        .add_system(skip_time_system.after(advance_time_system))
+        .add_system(update_sky_system.after(skip_time_system));
+    }
+}
+
+fn create_lights_system(mut commands: Commands) {
+    let light = |illuminance| DirectionalLight {
+        illuminance,
+        shadows_enabled: true,
+        // a single map in place of cascades; see `SHADOW_SIZE`
+        shadow_projection: OrthographicProjection {
+            left: -SHADOW_SIZE,
+            right: SHADOW_SIZE,
+            bottom: -SHADOW_SIZE,
+            top: SHADOW_SIZE,
+            near: -SHADOW_SIZE * 2.0,
+            far: SHADOW_SIZE * 2.0,
             ..default()
         },
-        transform: Transform::from_xyz(0.0, 80.0, 0.0),
         ..default()
-    });
+    };
+
+    // only one directional light is drawn at once, so the one below the horizon is hidden
+    commands
+        .spawn_bundle(DirectionalLightBundle {
+            directional_light: light(SUN_ILLUMINANCE),
+            ..default()
+        })
+        .insert(Sun);
+    commands
+        .spawn_bundle(DirectionalLightBundle {
+            directional_light: DirectionalLight {
+                color: Color::rgb(0.7, 0.75, 1.0),
+                ..light(MOON_ILLUMINANCE)
+            },
+            ..default()
+        })
+        .insert(Moon);
+}
+
+fn advance_time_system(time: Res<Time>, mut time_of_day: ResMut<TimeOfDay>) {
+    if time_of_day.paused || time_of_day.day_length <= 0.0 {
+        return;
+    }
+
+    time_of_day.time =
+        (time_of_day.time + time.delta_seconds() / time_of_day.day_length).rem_euclid(1.0);
+}
+
+fn skip_time_system(actions: Res<ActionState>, mut time_of_day: ResMut<TimeOfDay>) {
+    if actions.just_pressed(Action::SkipHour) {
+        let hours = time_of_day.hours();
+        time_of_day.set_hours(hours + 1.0);
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn update_sky_system(
+    time_of_day: Res<TimeOfDay>,
+    mut clear_color: ResMut<ClearColor>,
+    mut ambient: ResMut<AmbientLight>,
+    shadow_map: Res<DirectionalLightShadowMap>,
+    players: Query<&Transform, (With<Player>, Without<Sun>, Without<Moon>)>,
+    mut sun: Query<
+        (&mut DirectionalLight, &mut Transform, &mut Visibility),
+        (With<Sun>, Without<Moon>),
+    >,
+    mut moon: Query<(&mut DirectionalLight, &mut Transform, &mut Visibility), With<Moon>>,
+) {
+    let (clear, ambient_color, brightness) = time_of_day.sky();
+    clear_color.0 = clear;
+    ambient.color = ambient_color;
+    ambient.brightness = brightness;
+
+    let center = players
+        .iter()
+        .next()
+        .map(|t| t.translation)
+        .unwrap_or_default();
+    let sun_dir = time_of_day.sun_direction();
+
+    for (dir, max, (mut light, mut transform, mut visibility)) in [
+        (sun_dir, SUN_ILLUMINANCE, sun.single_mut()),
+        (-sun_dir, MOON_ILLUMINANCE, moon.single_mut()),
+    ] {
+        // fades out as it sets
+        let elevation = dir.y;
+        visibility.is_visible = elevation > 0.0;
+        light.illuminance = max * (elevation * 4.0).clamp(0.0, 1.0);
+
+        let rotation = Transform::default().looking_at(-dir, Vec3::Y).rotation;
+        // the shadow map follows the player in whole texels so that the shadow edges don't shimmer
+        let texel = SHADOW_SIZE * 2.0 / shadow_map.size as f32;
+        let local = (rotation.inverse() * center / texel).round() * texel;
+        *transform = Transform {
+            translation: rotation * local,
+            rotation,
+            ..default()
+        };
+    }
 }
@@ -0,0 +1,260 @@
+use crate::{
+    cache::ChunkCache,
+    chunk::{apply_edits, mesh_voxels, Chunk, ChunkMesh, ChunkShape, CHUNK_VOXELS},
+    edit::PaddedEdits,
+    generator::{Occupancy, TerrainGen},
+    voxel::Voxel,
+};
+use bevy::prelude::*;
+use block_mesh::ndshape::ConstShape;
+use std::sync::Arc;
+
+/// Coarsest level of detail; a node at the level spans 2^MAX_LOD chunks per axis
+#[cfg(target_arch = "wasm32")]
+pub const MAX_LOD: u32 = 2;
+#[cfg(not(target_arch = "wasm32"))]
+pub const MAX_LOD: u32 = 3;
+
+/// Fraction of the split distance beyond which split nodes are merged again
+const MERGE_MARGIN: f32 = 0.25;
+
+/// Node of the level-of-detail octree
+///
+/// A node at level `lod` covers 2^lod chunks per axis with the same number of voxels as a chunk,
+/// sampling every 2^lod-th voxel. Level 0 is the chunk itself.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Component)]
+pub struct LodChunk {
+    pub lod: u32,
+    pub x: i64,
+    pub y: i64,
+    pub z: i64,
+}
+
+impl From<Chunk> for LodChunk {
+    fn from(chunk: Chunk) -> Self {
+        Self {
+            lod: 0,
+            x: chunk.x,
+            y: chunk.y,
+            z: chunk.z,
+        }
+    }
+}
+
+impl LodChunk {
+    pub fn new(lod: u32, x: i64, y: i64, z: i64) -> Self {
+        Self { lod, x, y, z }
+    }
+
+    /// Node at the level containing the position in the world coordinate
+    pub fn from_world_coord(lod: u32, coord: Vec3) -> Self {
+        let node_coord = (coord / (Chunk::size() * (1 << lod) as f32)).floor();
+
+        Self::new(
+            lod,
+            node_coord.x as i64,
+            node_coord.y as i64,
+            node_coord.z as i64,
+        )
+    }
+
+    /// Number of chunks per axis
+    pub fn scale(&self) -> i64 {
+        1 << self.lod
+    }
+
+    /// Full-resolution chunk if the node is at level 0
+    pub fn chunk(&self) -> Option<Chunk> {
+        (self.lod == 0).then(|| Chunk::new(self.x, self.y, self.z))
+    }
+
+    /// Size in the world coordinate
+    pub fn size(&self) -> Vec3 {
+        Chunk::size() * self.scale() as f32
+    }
+
+    /// Position in the world coordinate
+    pub fn position(&self) -> Vec3 {
+        Vec3::new(self.x as f32, self.y as f32, self.z as f32) * self.size()
+    }
+
+    /// Center in the world coordinate
+    pub fn center(&self) -> Vec3 {
+        self.position() + self.size() * 0.5
+    }
+
+    /// Distance from the position to the closest point of the node
+    pub fn distance(&self, pos: Vec3) -> f32 {
+        let min = self.position();
+        let max = min + self.size();
+        pos.clamp(min, max).distance(pos)
+    }
+
+    /// Range of the chunk coordinate covered by the node, max exclusive
+    fn chunk_range(&self) -> ([i64; 3], [i64; 3]) {
+        let s = self.scale();
+        let min = [self.x * s, self.y * s, self.z * s];
+        (min, min.map(|v| v + s))
+    }
+
+    /// The nodes cover some common chunks
+    pub fn overlaps(&self, other: &LodChunk) -> bool {
+        let (min_a, max_a) = self.chunk_range();
+        let (min_b, max_b) = other.chunk_range();
+        (0..3).all(|i| min_a[i] < max_b[i] && min_b[i] < max_a[i])
+    }
+
+    /// The chunk is in the node or shares a face with it
+    pub fn touches(&self, chunk: &Chunk) -> bool {
+        let (min, max) = self.chunk_range();
+        let c = [chunk.x, chunk.y, chunk.z];
+        (0..3).all(|i| min[i] - 1 <= c[i] && c[i] <= max[i])
+    }
+
+    /// Range of the voxel coordinate in y
+    pub fn voxel_range_y(&self) -> (i64, i64) {
+        let (min, max) = self.chunk_range();
+        let size = CHUNK_VOXELS.y as i64;
+        (min[1] * size, max[1] * size - 1)
+    }
+
+    /// Nodes at the next finer level covering the node
+    pub fn children(&self) -> Vec<LodChunk> {
+        if self.lod == 0 {
+            return vec![];
+        }
+
+        (0..8)
+            .map(|i| {
+                LodChunk::new(
+                    self.lod - 1,
+                    self.x * 2 + (i & 1),
+                    self.y * 2 + ((i >> 1) & 1),
+                    self.z * 2 + ((i >> 2) & 1),
+                )
+            })
+            .collect()
+    }
+
+    /// The generated voxels may have surfaces to draw
+    fn has_surface(&self, gen: &TerrainGen) -> bool {
+        match self.chunk() {
+            Some(chunk) => chunk.has_surface(gen),
+            None => gen.occupancy(self.voxel_range_y()) == Occupancy::Mixed,
+        }
+    }
+
+    /// Generated voxels laid out in `ChunkShape` before modifications
+    ///
+    /// Unlike chunks, coarse nodes keep the padding empty so that the faces on their borders close the seams to the
+    /// neighbours at the other levels.
+    fn generate_voxels(&self, gen: &TerrainGen) -> Vec<Voxel> {
+        if let Some(chunk) = self.chunk() {
+            return chunk.generate_voxels(gen);
+        }
+
+        let step = self.scale();
+        let base = [
+            self.x * step * CHUNK_VOXELS.x as i64,
+            self.y * step * CHUNK_VOXELS.y as i64,
+            self.z * step * CHUNK_VOXELS.z as i64,
+        ];
+        let size = [CHUNK_VOXELS.x, CHUNK_VOXELS.y, CHUNK_VOXELS.z];
+
+        // sample the column once for all the voxels stacked on it
+        let columns: Vec<_> = (0..size[2] as i64)
+            .flat_map(|z| (0..size[0] as i64).map(move |x| (x, z)))
+            .map(|(x, z)| gen.column(base[0] + x * step, base[2] + z * step))
+            .collect();
+
+        (0..ChunkShape::SIZE)
+            .map(|i| {
+                let p = ChunkShape::delinearize(i);
+                if (0..3).any(|a| p[a] == 0 || p[a] == size[a] + 1) {
+                    // padding
+                    return Voxel::EMPTY;
+                }
+
+                let [x, y, z] = p.map(|v| v as i64 - 1);
+                let column = columns[(z * size[0] as i64 + x) as usize];
+                gen.voxel(
+                    base[0] + x * step,
+                    base[1] + y * step,
+                    base[2] + z * step,
+                    column,
+                )
+            })
+            .collect()
+    }
+
+    /// Build the meshes reusing the cached voxels; modifications are only reflected at level 0
+    pub fn generate_mesh(
+        &self,
+        gen: &TerrainGen,
+        edits: Option<&PaddedEdits>,
+        cache: &ChunkCache,
+    ) -> ChunkMesh {
+        if edits.is_none() && !self.has_surface(gen) {
+            // no surface to draw
+            return ChunkMesh::default();
+        }
+
+        let voxels = match cache.voxels(self) {
+            Some(v) => v,
+            None => {
+                let voxels = Arc::new(self.generate_voxels(gen));
+                cache.insert_voxels(self, voxels.clone());
+                voxels
+            }
+        };
+        let voxel_size = Chunk::voxel_size() * self.scale() as f32;
+        let empty_padding = self.lod > 0;
+
+        match edits {
+            Some(edits) => {
+                let mut voxels = (*voxels).clone();
+                apply_edits(&mut voxels, edits);
+                mesh_voxels(&voxels, voxel_size, empty_padding)
+            }
+            None => mesh_voxels(&voxels, voxel_size, empty_padding),
+        }
+    }
+}
+
+/// Choose the nodes to draw around the viewpoints given as pairs of a position and a detail range
+///
+/// Starting from the coarsest `roots`, a node is replaced by its children while it's closer to any viewpoint than
+/// its detail range scaled by the node size, so the detail doubles each time the distance halves.
+/// Nodes for which `shown` returns true were drawn as they are and only split within that distance, while the others
+/// stay split up to `MERGE_MARGIN` farther, so that nodes on the boundary don't flicker as a viewpoint moves back and
+/// forth. Splits don't depend on the roots, so the selections from different roots with the same viewpoints and
+/// `shown` never overlap at different levels. Nodes for which `keep` returns false are skipped along with their
+/// descendants.
+pub fn select(
+    roots: impl Iterator<Item = LodChunk>,
+    viewpoints: &[(Vec3, f32)],
+    shown: impl Fn(&LodChunk) -> bool,
+    keep: impl Fn(&LodChunk) -> bool,
+) -> Vec<LodChunk> {
+    let mut selected = vec![];
+    let mut stack: Vec<_> = roots.filter(|n| keep(n)).collect();
+
+    while let Some(node) = stack.pop() {
+        let margin = if shown(&node) {
+            1.0
+        } else {
+            1.0 + MERGE_MARGIN
+        };
+        let split = viewpoints
+            .iter()
+            .any(|(pos, range)| node.distance(*pos) < range * node.scale() as f32 / 2.0 * margin);
+
+        if node.lod > 0 && split {
+            stack.extend(node.children().into_iter().filter(|n| keep(n)));
+        } else {
+            selected.push(node);
+        }
+    }
+
+    selected
+}
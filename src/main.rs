@@ -1,10 +1,27 @@
 use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
 
+mod ao;
+mod biome;
+mod block;
+mod cache;
+mod camera;
+mod cave;
 mod chunk;
+mod controller;
+mod edit;
+mod fog;
+mod generator;
+mod heightmap;
+mod input;
 mod light;
+mod lod;
 mod map;
 mod player;
+mod render;
+mod save;
+mod scheduler;
+mod settings;
 mod terrain;
 mod voxel;
 
@@ -12,16 +29,15 @@ fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    spawn: Option<Res<save::PlayerSpawn>>,
 ) {
-    light::create_light(&mut commands);
-
-    player::create_camera(&mut commands);
-
     player::create_player(
         &mut commands,
         &mut meshes,
         &mut materials,
-        Transform::from_xyz(0.0, 100.0, 0.0),
+        spawn
+            .map(|s| s.0)
+            .unwrap_or_else(|| Transform::from_xyz(0.0, 100.0, 0.0)),
     );
 }
 
@@ -29,12 +45,29 @@ fn main() {
     App::new()
         .add_plugins(DefaultPlugins)
         .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
-        .add_plugin(terrain::TerrainGenPlugin)
+        .add_plugin(settings::SettingsPlugin)
+        .add_plugin(input::InputPlugin::default())
+        .add_plugin(camera::CameraPlugin)
+        .add_plugin(light::DayNightPlugin::default())
+        .add_plugin(render::TerrainRenderPlugin)
+        .add_plugin(fog::FogPlugin)
+        .add_plugin(terrain::TerrainGenPlugin::default())
+        .add_plugin(save::SavePlugin)
         // .add_plugin(RapierDebugRenderPlugin::default())
         .add_startup_system(setup)
-        .add_system(player::input_control_system)
-        .add_system(player::update_camera_system)
+        .add_system(player::input_control_system.before(controller::move_character_system))
+        .add_system(controller::move_character_system)
+        .add_system(player::turn_player_system)
+        .add_system(player::show_model_system)
+        .add_system(player::land_system.after(controller::move_character_system))
         .add_system(terrain::request_terrain_system)
         .add_system(terrain::render_terrain_system)
+        .add_system(edit::edit_terrain_system)
+        .add_system(terrain::modify_terrain_system.after(edit::edit_terrain_system))
+        .add_system(
+            terrain::dispatch_terrain_system
+                .after(terrain::request_terrain_system)
+                .after(terrain::modify_terrain_system),
+        )
         .run();
 }
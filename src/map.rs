@@ -1,4 +1,5 @@
-use noise::{NoiseFn, Perlin, Seedable};
+use noise::{NoiseFn, Perlin};
+use serde::{Deserialize, Serialize};
 
 pub fn local_level_cfg() -> GenConfig {
     GenConfig {
@@ -22,6 +23,51 @@ pub fn global_level_cfg() -> GenConfig {
     }
 }
 
+pub fn temperature_cfg() -> GenConfig {
+    GenConfig {
+        seed: 1,
+        redistribution: 1.0,
+        freq: 500.0,
+        octaves: 2,
+        max_width: 1_000_000,
+        origin: (500_000, 500_000),
+    }
+}
+
+pub fn moisture_cfg() -> GenConfig {
+    GenConfig {
+        seed: 2,
+        redistribution: 1.0,
+        freq: 500.0,
+        octaves: 2,
+        max_width: 1_000_000,
+        origin: (500_000, 500_000),
+    }
+}
+
+pub fn tunnel_cfg() -> GenConfig {
+    GenConfig {
+        seed: 3,
+        redistribution: 1.0,
+        freq: 25_000.0,
+        octaves: 2,
+        max_width: 1_000_000,
+        origin: (500_000, 500_000),
+    }
+}
+
+pub fn overhang_cfg() -> GenConfig {
+    GenConfig {
+        seed: 5,
+        redistribution: 1.0,
+        freq: 50_000.0,
+        octaves: 1,
+        max_width: 1_000_000,
+        origin: (500_000, 500_000),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GenConfig {
     pub seed: u32,
     pub redistribution: f64,
@@ -39,8 +85,8 @@ pub struct ProcGen {
 impl ProcGen {
     pub fn new(cfg: GenConfig) -> Self {
         Self {
-            perlin: Perlin::new().set_seed(cfg.seed),
-            cfg: cfg,
+            perlin: Perlin::new(cfg.seed),
+            cfg,
         }
     }
 
@@ -61,8 +107,29 @@ impl ProcGen {
             acc + modifier * self.perlin.get([nx * freq * power, ny * freq * power])
         });
 
-        (((value.powf(redist) + 1.0) / 2.0) as f32)
-            .max(0.0)
-            .min(1.0)
+        (((value.powf(redist) + 1.0) / 2.0) as f32).clamp(0.0, 1.0)
+    }
+
+    /// 3D variant of `gen`; `y` isn't shifted by the origin
+    pub fn gen3(&self, x: i64, y: i64, z: i64) -> f32 {
+        let x = x + self.cfg.origin.0;
+        let z = z + self.cfg.origin.1;
+
+        let redist = self.cfg.redistribution;
+        let freq = self.cfg.freq;
+        let octaves = self.cfg.octaves;
+
+        let nx = x as f64 / self.cfg.max_width as f64;
+        let ny = y as f64 / self.cfg.max_width as f64;
+        let nz = z as f64 / self.cfg.max_width as f64;
+
+        let value = (0..octaves).fold(0.0, |acc, n| {
+            let power = 2.0f64.powf(n as f64);
+            let modifier = 1.0 / power;
+            let f = freq * power;
+            acc + modifier * self.perlin.get([nx * f, ny * f, nz * f])
+        });
+
+        (((value.powf(redist) + 1.0) / 2.0) as f32).clamp(0.0, 1.0)
     }
 }
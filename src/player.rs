@@ -1,14 +1,62 @@
-use crate::terrain::Loader;
-use bevy::{
-    input::{keyboard::KeyCode, Input},
-    prelude::*,
+use crate::{
+    camera::{CameraController, CameraMode},
+    controller::{
+        CharacterBundle, CharacterController, Grounded, CAPSULE_HALF_HEIGHT, CAPSULE_RADIUS,
+    },
+    input::{Action, ActionState},
+    terrain::{LoadShape, Loader},
 };
-use bevy_rapier3d::prelude::*;
-use nalgebra::Vector3;
+use bevy::prelude::*;
+
+/// Landing speed in m/s from which a landing is reported; about a fall of three voxels
+const HARD_LANDING_SPEED: f32 = 13.0;
+
+/// Rate the model turns toward the direction of travel at
+const TURN_RATE: f32 = 10.0;
 
 #[derive(Component)]
 pub struct Player;
 
+/// Mesh of the player, turned toward the direction of travel while the collider stays upright; the visor faces -Z
+#[derive(Component)]
+pub struct PlayerModel;
+
+/// Walking speeds of the player in m/s by the direction relative to the view of the camera
+#[derive(Component, Debug, Clone)]
+pub struct MoveSpeeds {
+    pub forward: f32,
+    /// Sideways
+    pub strafe: f32,
+    /// Toward the camera
+    pub backpedal: f32,
+    /// Multiplier of the speeds while running
+    pub run: f32,
+}
+
+impl Default for MoveSpeeds {
+    fn default() -> Self {
+        Self {
+            forward: 10.0,
+            strafe: 8.0,
+            backpedal: 6.0,
+            run: 2.0,
+        }
+    }
+}
+
+impl MoveSpeeds {
+    /// Velocity in the view space of the movement, where -Z is forward
+    fn velocity(&self, dir: Vec2, run: bool) -> Vec3 {
+        let forward = if dir.y < 0.0 {
+            self.forward
+        } else {
+            self.backpedal
+        };
+        let run = if run { self.run } else { 1.0 };
+        Vec3::new(dir.x * self.strafe, 0.0, dir.y * forward) * run
+    }
+}
+
 pub fn create_player(
     commands: &mut Commands,
     meshes: &mut ResMut<Assets<Mesh>>,
@@ -18,93 +66,114 @@ pub fn create_player(
     commands
         .spawn()
         .insert(Player)
-        .insert(Loader::new())
-        .insert(RigidBody::Dynamic)
-        .insert(ExternalImpulse::default())
-        .insert(ExternalForce::default())
-        .insert(LockedAxes::ROTATION_LOCKED)
-        .insert(Friction {
-            coefficient: 1.0,
-            combine_rule: CoefficientCombineRule::Min,
-        })
-        .insert(Restitution {
-            coefficient: 0.0,
-            combine_rule: CoefficientCombineRule::Max,
-        })
-        .insert(AdditionalMassProperties::Mass(50.0))
-        .insert(Collider::ball(0.5))
-        .insert(GravityScale(3.0))
-        .insert(Ccd::enabled())
-        .insert(Velocity::zero())
-        .insert_bundle(PbrBundle {
-            mesh: meshes.add(shape::Box::new(1.0, 1.0, 1.0).into()),
-            material: materials.add(Color::RED.into()),
-            transform,
-            ..default()
+        .insert(Loader::new(LoadShape::default()))
+        .insert_bundle(CharacterBundle::default())
+        .insert(MoveSpeeds::default())
+        .insert_bundle(SpatialBundle::from_transform(transform))
+        .with_children(|parent| {
+            parent
+                .spawn_bundle(PbrBundle {
+                    // the shape of the collider
+                    mesh: meshes.add(
+                        shape::Capsule {
+                            radius: CAPSULE_RADIUS,
+                            depth: CAPSULE_HALF_HEIGHT * 2.0,
+                            ..default()
+                        }
+                        .into(),
+                    ),
+                    material: materials.add(Color::RED.into()),
+                    ..default()
+                })
+                .insert(PlayerModel)
+                .with_children(|parent| {
+                    // shows which way the player faces
+                    parent.spawn_bundle(PbrBundle {
+                        mesh: meshes.add(shape::Box::new(CAPSULE_RADIUS * 1.2, 0.15, 0.12).into()),
+                        material: materials.add(Color::rgb(0.1, 0.1, 0.15).into()),
+                        transform: Transform::from_xyz(
+                            0.0,
+                            CAPSULE_HALF_HEIGHT * 0.8,
+                            -CAPSULE_RADIUS,
+                        ),
+                        ..default()
+                    });
+                });
         });
 }
 
-pub fn create_camera(commands: &mut Commands) {
-    commands.spawn_bundle(Camera3dBundle {
-        transform: Transform::from_xyz(-3.0, 3.0, 10.0).looking_at(Vec3::ZERO, Vec3::Y),
-        ..Default::default()
-    });
-}
-
-pub fn update_camera_system(
-    players: Query<(&Player, &Transform)>,
-    mut cameras: Query<(&Camera, &mut Transform), Without<Player>>,
+pub fn input_control_system(
+    mut players: Query<(&mut CharacterController, &MoveSpeeds), With<Player>>,
+    actions: Res<ActionState>,
+    cameras: Query<&CameraController>,
 ) {
-    for (_, player_pos) in &players {
-        for (_, mut camera_pos) in &mut cameras {
-            *camera_pos =
-                Transform::from_translation(player_pos.translation + Vec3::new(10.0, 30.0, 20.0))
-                    .looking_at(player_pos.translation, Vec3::Y);
+    let camera = match cameras.iter().next() {
+        Some(c) => c,
+        None => return,
+    };
+    // walk relative to the direction the camera faces on the ground
+    let view = Quat::from_rotation_y(camera.yaw);
+
+    for (mut controller, speeds) in &mut players {
+        // the keys fly the camera instead
+        if camera.mode == CameraMode::FreeFly {
+            controller.walk = Vec3::ZERO;
+            controller.swim_up = false;
+            continue;
+        }
+
+        controller.walk = view * speeds.velocity(actions.movement, actions.pressed(Action::Sprint));
+        controller.swim_up = actions.pressed(Action::Jump);
+        if actions.just_pressed(Action::Jump) {
+            controller.jump();
         }
     }
 }
 
-pub fn input_control_system(
-    mut query: Query<(
-        &mut ExternalForce,
-        &mut ExternalImpulse,
-        &Velocity,
-        &Player,
-        &RapierRigidBodyHandle,
-    )>,
-    context: Res<RapierContext>,
-    input: Res<Input<KeyCode>>,
+/// Turn the model of the player toward the direction of travel
+pub fn turn_player_system(
+    time: Res<Time>,
+    players: Query<(&CharacterController, &Children), With<Player>>,
+    mut models: Query<&mut Transform, With<PlayerModel>>,
 ) {
-    for (mut force, mut impulse, velocity, _, handle) in &mut query {
-        let neg_z = if input.pressed(KeyCode::W) { -1.0 } else { 0.0 };
-        let pos_z = if input.pressed(KeyCode::S) { 1.0 } else { 0.0 };
-        let neg_x = if input.pressed(KeyCode::A) { -1.0 } else { 0.0 };
-        let pos_x = if input.pressed(KeyCode::D) { 1.0 } else { 0.0 };
-        let speed = if input.pressed(KeyCode::K) {
-            20.0
-        } else {
-            10.0
-        };
-        let target_vel = Vec3::new(pos_x + neg_x, 0.0, pos_z + neg_z) * speed;
-
-        force.force = (target_vel - velocity.linvel) * 1000.0;
-        force.force.y = 0.0;
+    for (controller, children) in &players {
+        let travel = Vec3::new(controller.velocity.x, 0.0, controller.velocity.z);
+        // keep facing the last direction when stopped
+        if travel.length() < 0.5 {
+            continue;
+        }
 
-        if input.pressed(KeyCode::J) {
-            let body = match context.bodies.get(handle.0) {
-                Some(b) => b,
-                None => continue,
-            };
+        let target = Transform::default().looking_at(travel, Vec3::Y).rotation;
+        let t = 1.0 - (-TURN_RATE * time.delta_seconds()).exp();
+        let mut models = models.iter_many_mut(children);
+        while let Some(mut model) = models.fetch_next() {
+            model.rotation = model.rotation.slerp(target, t);
+        }
+    }
+}
 
-            if vertically_stable(&body) {
-                impulse.impulse = Vec3::new(0.0, 1000.0, 0.0);
-            }
+/// Hide the model in first-person, where the camera is inside it
+pub fn show_model_system(
+    cameras: Query<&CameraController>,
+    mut models: Query<&mut Visibility, With<PlayerModel>>,
+) {
+    let first_person = cameras.iter().any(|c| c.mode == CameraMode::FirstPerson);
+    for mut visibility in &mut models {
+        if visibility.is_visible == first_person {
+            visibility.is_visible = !first_person;
         }
     }
 }
 
-fn vertically_stable(body: &rapier3d::prelude::RigidBody) -> bool {
-    let e1 = body.gravitational_potential_energy(0.001, Vector3::new(0.0, -9.81, 0.0));
-    let e2 = body.gravitational_potential_energy(0.002, Vector3::new(0.0, -9.81, 0.0));
-    e1 == e2
+/// Report the hard landings of the player; where landing effects hook in
+pub fn land_system(players: Query<&Grounded, (With<Player>, Added<Grounded>)>) {
+    for ground in &players {
+        if ground.impact_speed >= HARD_LANDING_SPEED {
+            debug!(
+                "Landed on {} at {:.1} m/s",
+                ground.block.block().name,
+                ground.impact_speed
+            );
+        }
+    }
 }
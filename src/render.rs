@@ -0,0 +1,173 @@
+use crate::block::TEXTURE_LAYERS;
+use bevy::{
+    pbr::{MaterialPipeline, MaterialPipelineKey},
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        mesh::{MeshVertexAttribute, MeshVertexBufferLayout},
+        render_resource::{
+            AddressMode, AsBindGroup, FilterMode, RenderPipelineDescriptor, SamplerDescriptor,
+            ShaderRef, SpecializedMeshPipelineError, VertexFormat,
+        },
+        texture::ImageSampler,
+    },
+};
+use std::collections::HashMap;
+
+pub use uniform::TerrainFog;
+
+/// Layer of the block texture array drawn on the vertex
+pub const ATTRIBUTE_TEXTURE_LAYER: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_TextureLayer", 0x4c61_7965, VertexFormat::Uint32);
+/// Brightness of the vertex from the ambient occlusion
+pub const ATTRIBUTE_OCCLUSION: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_Occlusion", 0x4f63_636c, VertexFormat::Float32);
+
+// the derive of `ShaderType` generates type checks at the module level that are never called
+#[allow(dead_code)]
+mod uniform {
+    use bevy::{prelude::*, render::render_resource::ShaderType};
+
+    /// Fog blending the terrain into the sky by the distance from the camera
+    #[derive(ShaderType, Debug, Clone, Copy, PartialEq, Default)]
+    pub struct TerrainFog {
+        /// Linear color
+        pub color: Vec4,
+        /// Distances the linear fog fades in between; no fog if `end` isn't past `start`
+        pub start: f32,
+        pub end: f32,
+        /// Density of the exponential squared fog, used instead of the linear fog if positive
+        pub density: f32,
+    }
+}
+
+/// Lit material sampling the block texture array with the layer of each vertex
+#[derive(AsBindGroup, TypeUuid, Debug, Clone)]
+#[uuid = "5f0b7c2e-8d1a-4e3b-9c6f-2a4d8e1b7f30"]
+pub struct TerrainMaterial {
+    #[texture(0, dimension = "2d_array")]
+    #[sampler(1)]
+    pub textures: Handle<Image>,
+    #[uniform(2)]
+    pub fog: TerrainFog,
+    pub alpha_mode: AlphaMode,
+}
+
+impl Material for TerrainMaterial {
+    fn vertex_shader() -> ShaderRef {
+        "shaders/terrain.wgsl".into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        "shaders/terrain.wgsl".into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        self.alpha_mode
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayout,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        let vertex_layout = layout.get_layout(&[
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
+            Mesh::ATTRIBUTE_UV_0.at_shader_location(2),
+            ATTRIBUTE_TEXTURE_LAYER.at_shader_location(3),
+            ATTRIBUTE_OCCLUSION.at_shader_location(4),
+        ])?;
+        descriptor.vertex.buffers = vec![vertex_layout];
+        Ok(())
+    }
+}
+
+/// Materials shared by every chunk and the meshes owned by each chunk
+///
+/// Meshes are removed from the assets as soon as their chunk is re-meshed or despawned instead of waiting for
+/// the handles to be dropped.
+pub struct TerrainRender {
+    /// Block textures stacked vertically, turned into an array texture once loaded
+    textures: Handle<Image>,
+    pub opaque: Handle<TerrainMaterial>,
+    /// Alpha-blended material of translucent blocks such as water
+    pub translucent: Handle<TerrainMaterial>,
+    meshes: HashMap<Entity, Vec<Handle<Mesh>>>,
+}
+
+impl FromWorld for TerrainRender {
+    fn from_world(world: &mut World) -> Self {
+        let textures = world.resource::<AssetServer>().load("textures/blocks.png");
+        let mut materials = world.resource_mut::<Assets<TerrainMaterial>>();
+
+        Self {
+            opaque: materials.add(TerrainMaterial {
+                textures: textures.clone(),
+                fog: TerrainFog::default(),
+                alpha_mode: AlphaMode::Opaque,
+            }),
+            translucent: materials.add(TerrainMaterial {
+                textures: textures.clone(),
+                fog: TerrainFog::default(),
+                alpha_mode: AlphaMode::Blend,
+            }),
+            textures,
+            meshes: HashMap::new(),
+        }
+    }
+}
+
+impl TerrainRender {
+    /// Add the mesh owned by the chunk entity
+    pub fn add_mesh(
+        &mut self,
+        entity: Entity,
+        mesh: Mesh,
+        meshes: &mut Assets<Mesh>,
+    ) -> Handle<Mesh> {
+        let handle = meshes.add(mesh);
+        self.meshes.entry(entity).or_default().push(handle.clone());
+        handle
+    }
+
+    /// Remove the meshes owned by the chunk entity
+    pub fn release(&mut self, entity: Entity, meshes: &mut Assets<Mesh>) {
+        for handle in self.meshes.remove(&entity).unwrap_or_default() {
+            meshes.remove(&handle);
+        }
+    }
+}
+
+pub struct TerrainRenderPlugin;
+
+impl Plugin for TerrainRenderPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(MaterialPlugin::<TerrainMaterial>::default())
+            .init_resource::<TerrainRender>()
+            // right after the assets are loaded so that the stacked image never reaches the GPU
+            .add_system_to_stage(CoreStage::PreUpdate, prepare_textures_system);
+    }
+}
+
+/// Reinterpret the loaded block textures as an array texture tiling across the quads
+fn prepare_textures_system(render: Res<TerrainRender>, mut images: ResMut<Assets<Image>>) {
+    let stacked = matches!(
+        images.get(&render.textures),
+        Some(image) if image.texture_descriptor.size.depth_or_array_layers != TEXTURE_LAYERS
+    );
+    if !stacked {
+        return;
+    }
+
+    let image = images.get_mut(&render.textures).unwrap();
+    image.reinterpret_stacked_2d_as_array(TEXTURE_LAYERS);
+    image.sampler_descriptor = ImageSampler::Descriptor(SamplerDescriptor {
+        address_mode_u: AddressMode::Repeat,
+        address_mode_v: AddressMode::Repeat,
+        mag_filter: FilterMode::Nearest,
+        min_filter: FilterMode::Nearest,
+        ..default()
+    });
+}
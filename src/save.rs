@@ -0,0 +1,285 @@
+use crate::{
+    chunk::{Chunk, CHUNK_VOXELS},
+    edit::{ChunkEdits, VoxelEdits},
+    generator::TerrainGen,
+    player::Player,
+    settings::WorldGenSettings,
+    voxel::Voxel,
+};
+use bevy::{app::AppExit, prelude::*};
+use serde::{Deserialize, Serialize};
+
+/// Interval of automatic saves in seconds
+const AUTOSAVE_INTERVAL: f32 = 30.0;
+
+/// Backend holding the serialized world
+pub trait SaveStorage: Send + Sync {
+    fn load(&self) -> Option<String>;
+
+    fn store(&self, data: &str) -> Result<(), String>;
+}
+
+/// Saves to a file on native
+#[cfg(not(target_arch = "wasm32"))]
+pub struct FileStorage {
+    path: std::path::PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileStorage {
+    pub fn new(path: impl Into<std::path::PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl SaveStorage for FileStorage {
+    fn load(&self) -> Option<String> {
+        std::fs::read_to_string(&self.path).ok()
+    }
+
+    fn store(&self, data: &str) -> Result<(), String> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+        }
+        std::fs::write(&self.path, data).map_err(|e| e.to_string())
+    }
+}
+
+/// Saves to the browser local storage on wasm
+#[cfg(target_arch = "wasm32")]
+pub struct LocalStorage {
+    key: String,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl LocalStorage {
+    pub fn new(key: impl Into<String>) -> Self {
+        Self { key: key.into() }
+    }
+
+    fn storage() -> Option<web_sys::Storage> {
+        web_sys::window()?.local_storage().ok()?
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl SaveStorage for LocalStorage {
+    fn load(&self) -> Option<String> {
+        Self::storage()?.get_item(&self.key).ok()?
+    }
+
+    fn store(&self, data: &str) -> Result<(), String> {
+        Self::storage()
+            .ok_or_else(|| "local storage is unavailable".to_string())?
+            .set_item(&self.key, data)
+            .map_err(|e| format!("{:?}", e))
+    }
+}
+
+pub struct WorldStorage(pub Box<dyn SaveStorage>);
+
+/// Where the player is placed at startup if the world is loaded from the save
+pub struct PlayerSpawn(pub Transform);
+
+pub struct AutosaveTimer(Timer);
+
+#[derive(Serialize, Deserialize)]
+struct SavedTransform {
+    translation: [f32; 3],
+    rotation: [f32; 4],
+}
+
+/// Modified voxels of a chunk, run-length encoded in x, y, z order
+///
+/// `None` marks the voxels left as generated.
+#[derive(Serialize, Deserialize)]
+struct SavedChunk {
+    x: i64,
+    y: i64,
+    z: i64,
+    runs: Vec<(u32, Option<Voxel>)>,
+}
+
+impl SavedChunk {
+    fn encode(chunk: &Chunk, edits: &ChunkEdits) -> Self {
+        let mut dense = vec![None; (CHUNK_VOXELS.x * CHUNK_VOXELS.y * CHUNK_VOXELS.z) as usize];
+        for ([x, y, z], voxel) in edits.iter() {
+            dense[(x + CHUNK_VOXELS.x * (y + CHUNK_VOXELS.y * z)) as usize] = Some(voxel);
+        }
+
+        let mut runs: Vec<(u32, Option<Voxel>)> = vec![];
+        for voxel in dense {
+            match runs.last_mut() {
+                Some((len, v)) if *v == voxel => *len += 1,
+                _ => runs.push((1, voxel)),
+            }
+        }
+
+        Self {
+            x: chunk.x,
+            y: chunk.y,
+            z: chunk.z,
+            runs,
+        }
+    }
+
+    /// The runs cover the chunk of the size exactly
+    fn validate(&self, chunk_voxels: [u32; 3]) -> Result<(), String> {
+        let total: u64 = self.runs.iter().map(|(len, _)| *len as u64).sum();
+        let expected = chunk_voxels.iter().map(|v| *v as u64).product::<u64>();
+        if total != expected {
+            return Err(format!(
+                "chunk ({}, {}, {}) has {} voxels instead of {}",
+                self.x, self.y, self.z, total, expected
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Apply the voxels in the voxel coordinate so that saves survive changes of the chunk size
+    fn decode(&self, chunk_voxels: [u32; 3], edits: &VoxelEdits) {
+        let [sx, sy, sz] = chunk_voxels.map(|v| v as i64);
+        let mut i = 0;
+
+        for (len, voxel) in &self.runs {
+            let voxel = match voxel {
+                Some(v) => *v,
+                None => {
+                    i += *len as i64;
+                    continue;
+                }
+            };
+
+            for i in i..i + *len as i64 {
+                let (x, y, z) = (i % sx, i / sx % sy, i / (sx * sy));
+                edits.set(self.x * sx + x, self.y * sy + y, self.z * sz + z, voxel);
+            }
+            i += *len as i64;
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct WorldSave {
+    chunk_voxels: [u32; 3],
+    settings: WorldGenSettings,
+    player: SavedTransform,
+    chunks: Vec<SavedChunk>,
+}
+
+impl WorldSave {
+    /// Reject the sizes and the runs the chunks can't be decoded with
+    fn validate(&self) -> Result<(), String> {
+        if self.chunk_voxels.contains(&0) {
+            return Err(format!("invalid chunk size {:?}", self.chunk_voxels));
+        }
+
+        self.chunks
+            .iter()
+            .try_for_each(|c| c.validate(self.chunk_voxels))
+    }
+}
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        // one save per seed
+        let seed = app
+            .world
+            .get_resource::<WorldGenSettings>()
+            .map(|s| s.seed)
+            .unwrap_or_default();
+        #[cfg(not(target_arch = "wasm32"))]
+        let storage = FileStorage::new(format!("saves/world-{}.ron", seed));
+        #[cfg(target_arch = "wasm32")]
+        let storage = LocalStorage::new(format!("mcp5.world.{}", seed));
+
+        app.insert_resource(WorldStorage(Box::new(storage)));
+        app.insert_resource(AutosaveTimer(Timer::from_seconds(AUTOSAVE_INTERVAL, true)));
+        app.add_startup_system_to_stage(StartupStage::PreStartup, load_world_system);
+        app.add_system_to_stage(CoreStage::Last, save_world_system);
+    }
+}
+
+pub fn load_world_system(mut commands: Commands, storage: Res<WorldStorage>) {
+    let data = match storage.0.load() {
+        Some(d) => d,
+        None => return,
+    };
+    let save: WorldSave = match ron::from_str(&data) {
+        Ok(s) => s,
+        Err(e) => {
+            warn!("Discarding the broken save: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = save.validate() {
+        warn!("Discarding the broken save: {}", e);
+        return;
+    }
+
+    let edits = VoxelEdits::default();
+    for chunk in &save.chunks {
+        chunk.decode(save.chunk_voxels, &edits);
+    }
+
+    // the world keeps the shape it's saved with
+    commands.insert_resource(edits);
+    commands.insert_resource(TerrainGen::new(&save.settings));
+    commands.insert_resource(save.settings);
+    commands.insert_resource(PlayerSpawn(Transform {
+        translation: save.player.translation.into(),
+        rotation: Quat::from_array(save.player.rotation),
+        ..default()
+    }));
+
+    info!("Loaded {} modified chunks", save.chunks.len());
+}
+
+pub fn save_world_system(
+    time: Res<Time>,
+    mut timer: ResMut<AutosaveTimer>,
+    mut exit: EventReader<AppExit>,
+    storage: Res<WorldStorage>,
+    settings: Res<WorldGenSettings>,
+    edits: Res<VoxelEdits>,
+    players: Query<&Transform, With<Player>>,
+) {
+    let exiting = exit.iter().count() > 0;
+    if !timer.0.tick(time.delta()).just_finished() && !exiting {
+        return;
+    }
+
+    let player = match players.iter().next() {
+        Some(p) => p,
+        None => return,
+    };
+
+    let save = WorldSave {
+        chunk_voxels: CHUNK_VOXELS.to_array(),
+        settings: settings.clone(),
+        player: SavedTransform {
+            translation: player.translation.to_array(),
+            rotation: player.rotation.to_array(),
+        },
+        chunks: edits
+            .snapshot()
+            .iter()
+            .map(|(chunk, edits)| SavedChunk::encode(chunk, edits))
+            .collect(),
+    };
+
+    let data = match ron::to_string(&save) {
+        Ok(d) => d,
+        Err(e) => {
+            warn!("Failed to serialize the world: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = storage.0.store(&data) {
+        warn!("Failed to save the world: {}", e);
+    }
+}
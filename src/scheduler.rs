@@ -0,0 +1,186 @@
+use crate::lod::LodChunk;
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticId, Diagnostics},
+    prelude::*,
+};
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+    sync::{
+        atomic::{AtomicBool, Ordering as AtomicOrdering},
+        Arc,
+    },
+};
+
+// Max generation tasks running at once
+#[cfg(target_arch = "wasm32")]
+pub const MAX_IN_FLIGHT: usize = 4;
+#[cfg(not(target_arch = "wasm32"))]
+pub const MAX_IN_FLIGHT: usize = 32;
+
+/// Priority of the requests jumping the queue, e.g. re-meshing modified chunks
+pub const URGENT: i64 = i64::MIN;
+
+pub const QUEUE_DEPTH: DiagnosticId =
+    DiagnosticId::from_u128(0x6d5a_2f3e_9a41_4c1b_8e2d_51f0_7c3a_9b10);
+pub const TASKS_IN_FLIGHT: DiagnosticId =
+    DiagnosticId::from_u128(0x6d5a_2f3e_9a41_4c1b_8e2d_51f0_7c3a_9b11);
+
+/// Set when the chunk is unloaded so that the task can skip the generation
+#[derive(Debug, Clone, Default)]
+pub struct CancelFlag(Arc<AtomicBool>);
+
+impl CancelFlag {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(AtomicOrdering::Relaxed)
+    }
+
+    fn cancel(&self) {
+        self.0.store(true, AtomicOrdering::Relaxed);
+    }
+}
+
+/// Generation task ready to be spawned
+pub struct Job {
+    pub task: u64,
+    pub entity: Entity,
+    pub chunk: LodChunk,
+    pub cancel: CancelFlag,
+}
+
+struct Queued {
+    /// Lower runs first
+    priority: i64,
+    seq: u64,
+    entity: Entity,
+    chunk: LodChunk,
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    // max-heap; the lowest priority and then the oldest request comes first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .priority
+            .cmp(&self.priority)
+            .then(other.seq.cmp(&self.seq))
+    }
+}
+
+/// Orders the chunk generation by priority and limits the tasks running at once
+#[derive(Default)]
+pub struct ChunkScheduler {
+    queue: BinaryHeap<Queued>,
+    /// Latest request of each chunk entity; older entries in the queue are skipped
+    queued: HashMap<Entity, u64>,
+    in_flight: HashMap<u64, (Entity, CancelFlag)>,
+    next_seq: u64,
+    next_task: u64,
+}
+
+impl ChunkScheduler {
+    /// Request the generation of the chunk; replaces the request of the entity in the queue
+    pub fn push(&mut self, entity: Entity, chunk: LodChunk, priority: i64) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.queued.insert(entity, seq);
+        self.queue.push(Queued {
+            priority,
+            seq,
+            entity,
+            chunk,
+        });
+    }
+
+    /// Drop the request of the entity and cancel its running tasks
+    pub fn cancel(&mut self, entity: Entity) {
+        self.queued.remove(&entity);
+        for (e, cancel) in self.in_flight.values() {
+            if *e == entity {
+                cancel.cancel();
+            }
+        }
+    }
+
+    /// Recompute the priorities of the queued requests; urgent ones stay urgent
+    pub fn reprioritize(&mut self, priority: impl Fn(&LodChunk) -> i64) {
+        let queued = &self.queued;
+        self.queue = std::mem::take(&mut self.queue)
+            .into_iter()
+            .filter(|q| queued.get(&q.entity) == Some(&q.seq))
+            .map(|q| Queued {
+                priority: if q.priority == URGENT {
+                    URGENT
+                } else {
+                    priority(&q.chunk)
+                },
+                ..q
+            })
+            .collect();
+    }
+
+    /// Take the request to run next unless too many tasks are running
+    pub fn pop(&mut self) -> Option<Job> {
+        if self.in_flight.len() >= MAX_IN_FLIGHT {
+            return None;
+        }
+
+        while let Some(q) = self.queue.pop() {
+            if self.queued.get(&q.entity) != Some(&q.seq) {
+                // replaced or cancelled
+                continue;
+            }
+            self.queued.remove(&q.entity);
+
+            let task = self.next_task;
+            self.next_task += 1;
+            let cancel = CancelFlag::default();
+            self.in_flight.insert(task, (q.entity, cancel.clone()));
+
+            return Some(Job {
+                task,
+                entity: q.entity,
+                chunk: q.chunk,
+                cancel,
+            });
+        }
+
+        None
+    }
+
+    /// The result of the task is received
+    pub fn finish(&mut self, task: u64) {
+        self.in_flight.remove(&task);
+    }
+
+    /// Number of the requests waiting in the queue
+    pub fn queue_depth(&self) -> usize {
+        self.queued.len()
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+}
+
+pub fn setup_diagnostics_system(mut diagnostics: ResMut<Diagnostics>) {
+    diagnostics.add(Diagnostic::new(QUEUE_DEPTH, "terrain_queue_depth", 20));
+    diagnostics.add(Diagnostic::new(
+        TASKS_IN_FLIGHT,
+        "terrain_tasks_in_flight",
+        20,
+    ));
+}
@@ -0,0 +1,193 @@
+use crate::{
+    cave::{CaveConfig, CaveGen},
+    heightmap::LevelGen,
+    map::{global_level_cfg, local_level_cfg, moisture_cfg, temperature_cfg, GenConfig},
+};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Settings file read at startup on native
+#[cfg(not(target_arch = "wasm32"))]
+const SETTINGS_PATH: &str = "worldgen.ron";
+
+/// Parameters of the procedural world generation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorldGenSettings {
+    /// World seed; added to the seed of each level map
+    pub seed: u32,
+    /// Voxels above the terrain and below the level are filled with water
+    pub sea_level: i64,
+    pub local_level: GenConfig,
+    pub global_level: GenConfig,
+    pub temperature: GenConfig,
+    pub moisture: GenConfig,
+    pub caves: CaveConfig,
+}
+
+impl Default for WorldGenSettings {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            sea_level: 22,
+            local_level: local_level_cfg(),
+            global_level: global_level_cfg(),
+            temperature: temperature_cfg(),
+            moisture: moisture_cfg(),
+            caves: CaveConfig::default(),
+        }
+    }
+}
+
+impl WorldGenSettings {
+    pub fn level_gen(&self) -> LevelGen {
+        LevelGen::new(
+            self.seeded(&self.local_level),
+            self.seeded(&self.global_level),
+            self.seeded(&self.temperature),
+            self.seeded(&self.moisture),
+        )
+    }
+
+    pub fn cave_gen(&self) -> CaveGen {
+        CaveGen::new(CaveConfig {
+            tunnel: self.seeded(&self.caves.tunnel),
+            overhang: self.seeded(&self.caves.overhang),
+            ..self.caves.clone()
+        })
+    }
+
+    fn seeded(&self, cfg: &GenConfig) -> GenConfig {
+        GenConfig {
+            seed: cfg.seed.wrapping_add(self.seed),
+            ..cfg.clone()
+        }
+    }
+
+    /// Overwrite a parameter by name, e.g. `seed`, `sea_level` or `global_level.octaves`
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        fn parse<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+            value
+                .parse()
+                .map_err(|_| format!("invalid value `{}`", value))
+        }
+
+        let caves = &mut self.caves;
+        match key {
+            "caves.density" => return parse(value).map(|v| caves.density = v),
+            "caves.min_y" => return parse(value).map(|v| caves.min_y = v),
+            "caves.max_y" => return parse(value).map(|v| caves.max_y = v),
+            "caves.overhang_amplitude" => {
+                return parse(value).map(|v| caves.overhang_amplitude = v)
+            }
+            _ => {}
+        }
+
+        let (cfg, field) = match key.split_once('.') {
+            Some(("local_level", field)) => (&mut self.local_level, field),
+            Some(("global_level", field)) => (&mut self.global_level, field),
+            Some(("temperature", field)) => (&mut self.temperature, field),
+            Some(("moisture", field)) => (&mut self.moisture, field),
+            None if key == "seed" => {
+                self.seed = parse(value)?;
+                return Ok(());
+            }
+            None if key == "sea_level" => {
+                self.sea_level = parse(value)?;
+                return Ok(());
+            }
+            _ => return Err(format!("unknown parameter `{}`", key)),
+        };
+
+        match field {
+            "seed" => cfg.seed = parse(value)?,
+            "redistribution" => cfg.redistribution = parse(value)?,
+            "freq" => cfg.freq = parse(value)?,
+            "octaves" => cfg.octaves = parse(value)?,
+            _ => return Err(format!("unknown parameter `{}`", key)),
+        }
+
+        Ok(())
+    }
+
+    /// Load the settings file and apply the overrides given by command-line args
+    ///
+    /// Args are in the form of `--seed 42` or `--global_level.octaves=5`.
+    /// `--settings <path>` specifies the settings file.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn load() -> Self {
+        let mut overrides = vec![];
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let arg = match arg.strip_prefix("--") {
+                Some(a) => a.to_string(),
+                None => {
+                    warn!("Ignoring the argument `{}`", arg);
+                    continue;
+                }
+            };
+            match arg.split_once('=') {
+                Some((key, value)) => overrides.push((key.to_string(), value.to_string())),
+                None => match args.next() {
+                    Some(value) => overrides.push((arg, value)),
+                    None => warn!("Missing the value of `--{}`", arg),
+                },
+            }
+        }
+
+        let path = overrides
+            .iter()
+            .find(|(key, _)| key == "settings")
+            .map(|(_, value)| value.as_str())
+            .unwrap_or(SETTINGS_PATH);
+        let mut settings = match std::fs::read_to_string(path) {
+            Ok(data) => ron::from_str(&data).unwrap_or_else(|e| {
+                warn!("Ignoring the broken settings `{}`: {}", path, e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        };
+
+        settings.apply(overrides.iter().filter(|(key, _)| key != "settings"));
+        settings
+    }
+
+    /// Apply the overrides given by URL query params, e.g. `?seed=42&global_level.octaves=5`
+    #[cfg(target_arch = "wasm32")]
+    fn load() -> Self {
+        let query = web_sys::window()
+            .and_then(|w| w.location().search().ok())
+            .unwrap_or_default();
+        let overrides: Vec<_> = query
+            .trim_start_matches('?')
+            .split('&')
+            .filter_map(|param| param.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        let mut settings = Self::default();
+        settings.apply(overrides.iter());
+        settings
+    }
+
+    fn apply<'a>(&mut self, overrides: impl Iterator<Item = &'a (String, String)>) {
+        for (key, value) in overrides {
+            if let Err(e) = self.set(key, value) {
+                warn!("Ignoring `{}`: {}", key, e);
+            }
+        }
+    }
+}
+
+/// Loads `WorldGenSettings`; needs to be added before the plugins using it
+pub struct SettingsPlugin;
+
+impl Plugin for SettingsPlugin {
+    fn build(&self, app: &mut App) {
+        let settings = WorldGenSettings::load();
+
+        info!("World seed: {}", settings.seed);
+
+        app.insert_resource(settings);
+    }
+}
@@ -1,27 +1,105 @@
-use crate::chunk::Chunk;
-use bevy::{prelude::*, tasks::AsyncComputeTaskPool};
+use crate::{
+    cache::{ChunkCache, CACHE_BUDGET},
+    chunk::{Chunk, ChunkMesh},
+    edit::{ChunkModified, VoxelEdits},
+    generator::{Occupancy, TerrainGen},
+    lod::{self, LodChunk, MAX_LOD},
+    render::TerrainRender,
+    scheduler::{
+        setup_diagnostics_system, ChunkScheduler, Job, MAX_IN_FLIGHT, QUEUE_DEPTH, TASKS_IN_FLIGHT,
+        URGENT,
+    },
+    settings::WorldGenSettings,
+};
+use bevy::{
+    diagnostic::Diagnostics,
+    prelude::*,
+    render::primitives::{Aabb, Frustum},
+    tasks::AsyncComputeTaskPool,
+};
 use bevy_rapier3d::prelude::*;
 use crossbeam_channel::{bounded, Receiver, Sender};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+
+// Range of the full-resolution chunks in chunks
+#[cfg(target_arch = "wasm32")]
+const LOAD_RANGE: f32 = 4.0;
+#[cfg(not(target_arch = "wasm32"))]
+const LOAD_RANGE: f32 = 6.0;
+
+/// Space kept loaded around a loader, tested against the centers of the selected nodes
+#[derive(Debug, Clone, Copy)]
+pub struct LoadShape {
+    pub radius: f32,
+    /// Half extent in y of a horizontal cylinder; `None` for a sphere
+    pub half_height: Option<f32>,
+}
+
+impl Default for LoadShape {
+    fn default() -> Self {
+        let radius = Chunk::size().x * LOAD_RANGE * (1 << MAX_LOD) as f32;
+
+        // terrain spreads horizontally
+        Self {
+            radius,
+            half_height: Some(radius / 4.0),
+        }
+    }
+}
+
+impl LoadShape {
+    /// The offset from the loader is inside the shape grown by the margin
+    fn contains(&self, offset: Vec3, margin: f32) -> bool {
+        match self.half_height {
+            Some(h) => {
+                offset.x.hypot(offset.z) <= self.radius + margin && offset.y.abs() <= h + margin
+            }
+            None => offset.length() <= self.radius + margin,
+        }
+    }
+
+    /// Half size of the bounding box
+    fn extent(&self) -> Vec3 {
+        Vec3::new(
+            self.radius,
+            self.half_height.unwrap_or(self.radius),
+            self.radius,
+        )
+    }
+}
 
+/// Keeps the terrain around the entity loaded; every loader shares the chunks in its range with the others
 #[derive(Debug, Component)]
 pub struct Loader {
-    load_range: Vec3,
+    /// Nodes are split into finer ones within the distance scaled by their size
+    detail_range: f32,
+    shape: LoadShape,
+    /// Loaded nodes are kept until they're farther than the shape by the margin, so that nodes on the border
+    /// don't flicker as the loader moves back and forth
+    unload_margin: f32,
     update_range: Vec3,
     last_pos: Option<Vec3>,
-    chunks: HashMap<Chunk, Entity>,
+    /// Nodes in range at the last update
+    nodes: HashSet<LodChunk>,
 }
 
 impl Loader {
-    pub fn new() -> Self {
+    pub fn new(shape: LoadShape) -> Self {
         Self {
-            load_range: Chunk::size() * 4.0,
+            // the levels of detail fill the shape
+            detail_range: shape.radius / (1 << MAX_LOD) as f32,
+            shape,
+            unload_margin: Chunk::size().x * (1 << MAX_LOD) as f32 * 0.5,
             update_range: Chunk::size() * 0.5,
             last_pos: None,
-            chunks: HashMap::new(),
+            nodes: HashSet::new(),
         }
     }
 
+    pub fn shape(&self) -> &LoadShape {
+        &self.shape
+    }
+
     pub fn try_update(&mut self, new_pos: Vec3) -> bool {
         match self.last_pos.as_mut() {
             Some(last_pos) => {
@@ -45,43 +123,185 @@ impl Loader {
         }
     }
 
-    fn range(&self) -> Option<Range> {
-        self.last_pos
-            .map(|last_pos| Range::new(last_pos - self.load_range, last_pos + self.load_range))
+    /// Coarsest nodes reaching into the shape grown by the margin
+    fn roots(&self) -> Vec<LodChunk> {
+        let pos = match self.last_pos {
+            Some(p) => p,
+            None => return vec![],
+        };
+
+        let extent = self.shape.extent() + self.unload_margin;
+        let node_min = LodChunk::from_world_coord(MAX_LOD, pos - extent);
+        let node_max = LodChunk::from_world_coord(MAX_LOD, pos + extent);
+        (node_min.x..=node_max.x)
+            .flat_map(|x| {
+                (node_min.y..=node_max.y)
+                    .flat_map(move |y| (node_min.z..=node_max.z).map(move |z| (x, y, z)))
+            })
+            .map(|(x, y, z)| LodChunk::new(MAX_LOD, x, y, z))
+            .filter(|n| {
+                // the closest point of the node
+                let closest = pos.clamp(n.position(), n.position() + n.size());
+                self.shape.contains(closest - pos, self.unload_margin)
+            })
+            .collect()
     }
+
+    /// Keep the selected nodes whose centers are in the shape, and the loaded ones within the margin
+    fn update_nodes(&mut self, selected: Vec<LodChunk>) -> HashSet<LodChunk> {
+        let pos = match self.last_pos {
+            Some(p) => p,
+            None => return HashSet::new(),
+        };
+
+        let nodes: HashSet<_> = selected
+            .into_iter()
+            .filter(|n| {
+                let margin = if self.nodes.contains(n) {
+                    self.unload_margin
+                } else {
+                    0.0
+                };
+                self.shape.contains(n.center() - pos, margin)
+            })
+            .collect();
+
+        self.nodes = nodes.clone();
+        nodes
+    }
+}
+
+struct LoadedChunk {
+    entity: Entity,
+    /// Number of loaders having the chunk in range
+    refs: usize,
+}
+
+/// Chunk released by every loader
+struct RetiringChunk {
+    chunk: LodChunk,
+    entity: Entity,
+    /// The chunk got its first mesh before it was released
+    drawn: bool,
 }
 
-struct Range {
-    min: Vec3,
-    max: Vec3,
+/// Chunks loaded by any loader
+#[derive(Default)]
+pub struct LoadedChunks {
+    chunks: HashMap<LodChunk, LoadedChunk>,
+    /// Chunks in the range of each loader
+    owners: HashMap<Entity, HashSet<LodChunk>>,
+    /// Nodes waiting for their first mesh
+    pending: HashSet<LodChunk>,
+    /// Nodes replaced by others at a different level; kept drawn until the replacements are ready
+    retiring: Vec<RetiringChunk>,
+    /// Meshes taken from the cache, drawn along with the results of the tasks
+    ready: Vec<ComputedChunk>,
+    /// Despawned chunk entities whose meshes are to be released
+    despawned: Vec<Entity>,
+    /// Modifications reached chunks that aren't loaded, e.g. skipped as full, so the loaders select their nodes again
+    reselect: bool,
 }
 
-impl Range {
-    fn new(min: Vec3, max: Vec3) -> Self {
-        Self { min, max }
+impl LoadedChunks {
+    fn entity(&self, chunk: &LodChunk) -> Option<Entity> {
+        self.chunks.get(chunk).map(|c| c.entity)
     }
 
-    fn contains(&self, pos: Vec3) -> bool {
-        self.min.x <= pos.x
-            && pos.x <= self.max.x
-            && self.min.y <= pos.y
-            && pos.y <= self.max.y
-            && self.min.z <= pos.z
-            && pos.z <= self.max.z
+    /// Replace the chunks owned by the loader and return the chunks to be generated
+    fn assign(&mut self, loader: Entity, chunks: HashSet<LodChunk>) -> Vec<LodChunk> {
+        let old = self.owners.remove(&loader).unwrap_or_default();
+        for chunk in old.difference(&chunks) {
+            self.release(chunk);
+        }
+
+        let mut added = vec![];
+        for chunk in chunks.difference(&old) {
+            if let Some(loaded) = self.chunks.get_mut(chunk) {
+                loaded.refs += 1;
+            } else if let Some(i) = self
+                .retiring
+                .iter()
+                .position(|r| &r.chunk == chunk && r.drawn)
+            {
+                // loaded again before it's replaced; the chunks never drawn are requested again as their results
+                // are dropped while they're retiring
+                let RetiringChunk { chunk, entity, .. } = self.retiring.swap_remove(i);
+                self.chunks.insert(chunk, LoadedChunk { entity, refs: 1 });
+            } else {
+                added.push(chunk.clone());
+            }
+        }
+        self.owners.insert(loader, chunks);
+
+        added
+    }
+
+    /// Drop all the chunks owned by the removed loader
+    fn remove_owner(&mut self, loader: Entity) {
+        for chunk in self.owners.remove(&loader).unwrap_or_default() {
+            self.release(&chunk);
+        }
+    }
+
+    fn release(&mut self, chunk: &LodChunk) {
+        let loaded = match self.chunks.get_mut(chunk) {
+            Some(c) => c,
+            None => return,
+        };
+        loaded.refs -= 1;
+        if loaded.refs == 0 {
+            let entity = loaded.entity;
+            self.chunks.remove(chunk);
+            let drawn = !self.pending.remove(chunk);
+            self.retiring.push(RetiringChunk {
+                chunk: chunk.clone(),
+                entity,
+                drawn,
+            });
+        }
+    }
+
+    /// Despawn the retiring nodes no longer covering a hole left by the pending ones
+    ///
+    /// Nodes never drawn cover nothing, so their requests are cancelled right away.
+    fn retire(&mut self, commands: &mut Commands, scheduler: &mut ChunkScheduler) {
+        let pending = &self.pending;
+        let despawned = &mut self.despawned;
+        self.retiring.retain(|r| {
+            let covering = r.drawn && pending.iter().any(|p| p.overlaps(&r.chunk));
+            if !covering {
+                scheduler.cancel(r.entity);
+                commands.entity(r.entity).despawn_recursive();
+                despawned.push(r.entity);
+            }
+            covering
+        });
     }
 }
 
 struct ComputedChunk {
+    /// `None` if the mesh is taken from the cache
+    task: Option<u64>,
     entity: Entity,
-    chunk: Chunk,
-    mesh: Mesh,
+    chunk: LodChunk,
+    revision: u64,
+    mesh: ChunkMesh,
 }
 
 impl ComputedChunk {
-    fn new(entity: Entity, chunk: Chunk, mesh: Mesh) -> Self {
+    fn new(
+        task: Option<u64>,
+        entity: Entity,
+        chunk: LodChunk,
+        revision: u64,
+        mesh: ChunkMesh,
+    ) -> Self {
         Self {
+            task,
             entity,
             chunk,
+            revision,
             mesh,
         }
     }
@@ -93,129 +313,499 @@ pub struct ComputedChunkReceiver(Receiver<ComputedChunk>);
 #[derive(Debug, Clone)]
 pub struct ComputedChunkSender(Sender<ComputedChunk>);
 
-pub struct TerrainGenPlugin;
+pub struct TerrainGenPlugin {
+    /// Memory budget of the cache of the generated chunks in bytes
+    pub cache_budget: usize,
+}
+
+impl Default for TerrainGenPlugin {
+    fn default() -> Self {
+        Self {
+            cache_budget: CACHE_BUDGET,
+        }
+    }
+}
 
 impl Plugin for TerrainGenPlugin {
     fn build(&self, app: &mut App) {
-        let (tx, rx) = bounded(100);
+        // never full as the results are received before more tasks are spawned
+        let (tx, rx) = bounded(MAX_IN_FLIGHT);
         app.insert_resource(ComputedChunkSender(tx));
         app.insert_resource(ComputedChunkReceiver(rx));
+        let settings = app
+            .world
+            .get_resource::<WorldGenSettings>()
+            .cloned()
+            .unwrap_or_default();
+        app.insert_resource(TerrainGen::new(&settings));
+        app.insert_resource(VoxelEdits::default());
+        app.insert_resource(LoadedChunks::default());
+        app.insert_resource(ChunkScheduler::default());
+        app.insert_resource(ChunkCache::new(self.cache_budget));
+        app.add_event::<ChunkModified>();
+        app.add_startup_system(setup_diagnostics_system);
     }
 }
 
-pub fn create_terrain(
-    commands: &mut Commands,
+fn generate_terrain(
+    job: Job,
     sender: ComputedChunkSender,
-    chunk: Chunk,
-) -> Entity {
+    gen: TerrainGen,
+    edits: VoxelEdits,
+    cache: ChunkCache,
+) {
     let task_pool = AsyncComputeTaskPool::get();
 
-    let entity = commands.spawn().insert(chunk.clone()).id();
-
     task_pool
         .spawn(async move {
-            let mesh = match chunk.generate_mesh() {
-                Some(m) => m,
-                None => return,
+            let Job {
+                task,
+                entity,
+                chunk,
+                cancel,
+            } = job;
+
+            let (revision, mesh) = if cancel.is_cancelled() {
+                // left the range while queued in the pool
+                (0, ChunkMesh::default())
+            } else {
+                let edits = chunk.chunk().and_then(|c| edits.padded(&c));
+                let revision = edits.as_ref().map(|e| e.revision()).unwrap_or(0);
+                let mesh = chunk.generate_mesh(&gen, edits.as_ref(), &cache);
+                cache.insert_mesh(&chunk, revision, mesh.clone());
+                (revision, mesh)
             };
 
-            let computed_chunk = ComputedChunk::new(entity, chunk, mesh);
+            // sent even without a mesh so that the task is finished and the node is no longer pending
+            let computed_chunk = ComputedChunk::new(Some(task), entity, chunk, revision, mesh);
             let _ = sender.0.send(computed_chunk);
         })
         .detach();
+}
+
+fn spawn_terrain(commands: &mut Commands, chunk: &LodChunk) -> Entity {
+    commands
+        .spawn()
+        .insert(chunk.clone())
+        .insert_bundle(SpatialBundle::from_transform(Transform::from_translation(
+            chunk.position(),
+        )))
+        .id()
+}
+
+pub fn create_terrain(
+    commands: &mut Commands,
+    scheduler: &mut ChunkScheduler,
+    chunk: LodChunk,
+    priority: i64,
+) -> Entity {
+    let entity = spawn_terrain(commands, &chunk);
+
+    scheduler.push(entity, chunk, priority);
 
     entity
 }
 
-pub fn render_terrain_system(
+/// Generation order of the chunk
+///
+/// Chunks right around the loaders come first as bodies stand on them, then the ones in the view of the camera.
+/// Chunks behind count up to twice as far.
+fn priority(
+    chunk: &LodChunk,
+    loaders: &[Vec3],
+    camera: Option<(&GlobalTransform, &Frustum)>,
+) -> i64 {
+    // far more than any distance in millimeters
+    const TIER: i64 = 1 << 40;
+
+    let center = chunk.center();
+    let (distance, pos) = loaders
+        .iter()
+        .map(|pos| (chunk.distance(*pos), *pos))
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .unwrap_or((0.0, center));
+
+    let (factor, visible) = match camera {
+        Some((transform, frustum)) => {
+            let aabb = Aabb::from_min_max(Vec3::ZERO, chunk.size());
+            let model = Mat4::from_translation(chunk.position());
+            (
+                1.5 - 0.5 * transform.forward().dot((center - pos).normalize_or_zero()),
+                frustum.intersects_obb(&aabb, &model, true),
+            )
+        }
+        None => (1.0, true),
+    };
+
+    let tier = if distance < Chunk::size().x {
+        0
+    } else if visible {
+        1
+    } else {
+        2
+    };
+
+    tier * TIER + (distance * factor * 1000.0) as i64
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn dispatch_terrain_system(
     loaders: Query<&Loader>,
+    cameras: Query<(&GlobalTransform, &Frustum), With<Camera>>,
+    mut scheduler: ResMut<ChunkScheduler>,
+    mut diagnostics: ResMut<Diagnostics>,
+    sender: Res<ComputedChunkSender>,
+    gen: Res<TerrainGen>,
+    edits: Res<VoxelEdits>,
+    cache: Res<ChunkCache>,
+) {
+    if scheduler.queue_depth() > 0 {
+        // the loaders may have moved and the camera may have turned
+        let positions: Vec<_> = loaders.iter().filter_map(|l| l.last_pos).collect();
+        let camera = cameras.iter().next();
+        scheduler.reprioritize(|c| priority(c, &positions, camera));
+    }
+
+    while let Some(job) = scheduler.pop() {
+        generate_terrain(
+            job,
+            (*sender).clone(),
+            (*gen).clone(),
+            (*edits).clone(),
+            (*cache).clone(),
+        );
+    }
+
+    diagnostics.add_measurement(QUEUE_DEPTH, || scheduler.queue_depth() as f64);
+    diagnostics.add_measurement(TASKS_IN_FLIGHT, || scheduler.in_flight() as f64);
+}
+
+pub fn render_terrain_system(
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut render: ResMut<TerrainRender>,
     mut commands: Commands,
+    mut loaded: ResMut<LoadedChunks>,
+    mut scheduler: ResMut<ChunkScheduler>,
     receiver: Res<ComputedChunkReceiver>,
+    edits: Res<VoxelEdits>,
 ) {
-        // assume there's only one loader
-    let loader = match loaders.iter().next() {
-        Some(l) => l,
-        None => return,
-    };
+    let ready = std::mem::take(&mut loaded.ready);
+    for chunk in ready.into_iter().chain(receiver.0.try_iter()) {
+        if let Some(task) = chunk.task {
+            scheduler.finish(task);
+        }
 
-    for chunk in receiver.0.try_iter() {
-        if !loader.chunks.contains_key(&chunk.chunk) {
+        if loaded.entity(&chunk.chunk) != Some(chunk.entity) {
             // chunk is already removed
             continue;
         }
+        if let Some(c) = chunk.chunk.chunk() {
+            if chunk.revision < edits.revision(&c) {
+                // outdated by newer modifications
+                continue;
+            }
+        }
+        loaded.pending.remove(&chunk.chunk);
+
+        render.release(chunk.entity, &mut meshes);
+        let mut entity = commands.entity(chunk.entity);
+        // the translucent mesh is drawn by a child
+        entity.despawn_descendants();
+
+        match chunk.mesh.opaque {
+            Some(mesh) => {
+                if chunk.chunk.lod == 0 {
+                    // distant terrain is out of reach
+                    entity
+                        .insert(ColliderMassProperties::Density(100000.0))
+                        .insert(
+                            Collider::from_bevy_mesh(&mesh, &ComputedColliderShape::TriMesh)
+                                .unwrap(),
+                        );
+                }
+                entity
+                    .insert(render.add_mesh(chunk.entity, mesh, &mut meshes))
+                    .insert(render.opaque.clone());
+            }
+            None => {
+                // every opaque voxel in the chunk is removed
+                entity.remove::<Collider>().remove::<Handle<Mesh>>();
+            }
+        }
 
-        commands
-            .entity(chunk.entity)
-            .insert(ColliderMassProperties::Density(100000.0))
-            .insert(Collider::from_bevy_mesh(&chunk.mesh, &ComputedColliderShape::TriMesh).unwrap())
-            .insert_bundle(PbrBundle {
-                mesh: meshes.add(chunk.mesh),
-                material: materials.add(Color::WHITE.into()),
-                transform: Transform::from_translation(chunk.chunk.position()),
+        if let Some(mesh) = chunk.mesh.translucent {
+            let bundle = MaterialMeshBundle {
+                mesh: render.add_mesh(chunk.entity, mesh, &mut meshes),
+                material: render.translucent.clone(),
                 ..default()
+            };
+            entity.with_children(|parent| {
+                parent.spawn_bundle(bundle);
             });
+        }
+    }
+
+    loaded.retire(&mut commands, &mut scheduler);
+    for entity in std::mem::take(&mut loaded.despawned) {
+        render.release(entity, &mut meshes);
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn request_terrain_system(
-    mut loaders: Query<(&Transform, &mut Loader)>,
+    mut loaders: Query<(Entity, &Transform, &mut Loader)>,
+    cameras: Query<(&GlobalTransform, &Frustum), With<Camera>>,
     mut commands: Commands,
-    sender: Res<ComputedChunkSender>,
+    mut loaded: ResMut<LoadedChunks>,
+    mut scheduler: ResMut<ChunkScheduler>,
+    gen: Res<TerrainGen>,
+    edits: Res<VoxelEdits>,
+    cache: Res<ChunkCache>,
 ) {
-    // assume there's only one loader
-    let (transform, mut loader) = match loaders.iter_mut().next() {
-        Some((t, l)) => (*t, l),
-        None => return,
-    };
+    let mut needs_update = false;
+    for (_, transform, mut loader) in &mut loaders {
+        needs_update |= loader.try_update(transform.translation);
+    }
 
-    let pos = transform.translation;
+    let removed: Vec<_> = loaded
+        .owners
+        .keys()
+        .filter(|e| !loaders.contains(**e))
+        .copied()
+        .collect();
+    for loader in removed {
+        loaded.remove_owner(loader);
+        needs_update = true;
+    }
+    needs_update |= std::mem::take(&mut loaded.reselect);
 
-    if !loader.try_update(pos) {
+    if !needs_update {
         // no updates required
         return;
     }
 
-    let range = match loader.range() {
-        Some(l) => l,
-        None => return,
+    // every loader refines the nodes around the others too so that the levels agree where ranges overlap
+    let viewpoints: Vec<_> = loaders
+        .iter()
+        .filter_map(|(_, _, l)| l.last_pos.map(|pos| (pos, l.detail_range)))
+        .collect();
+    let modified = edits.chunks();
+    let keep = |n: &LodChunk| {
+        gen.occupancy(n.voxel_range_y()) == Occupancy::Mixed
+            || modified.iter().any(|c| n.touches(c))
     };
 
-    loader.chunks.retain(|chunk, entity| {
-        let in_range = range.contains(chunk.position());
-        if !in_range {
-            commands.entity(*entity).despawn();
-        }
-        in_range
-    });
+    // the nodes drawn before this update, the same for every loader so that their selections agree
+    let shown: HashSet<_> = loaded.chunks.keys().cloned().collect();
 
-    let chunk_min = Chunk::from_world_coord(range.min);
-    let chunk_max = Chunk::from_world_coord(range.max);
+    let mut columns = HashSet::new();
+    let mut added = vec![];
 
-    let mut chunks: Vec<_> = (chunk_min.x..=chunk_max.x)
-        .map(|x| {
-            (chunk_min.y..=chunk_max.y)
-                .map(move |y| (chunk_min.z..=chunk_max.z).map(move |z| (x, y, z)))
-                .flatten()
-        })
-        .flatten()
-        .map(|(x, y, z)| Chunk::new(x, y, z))
-        .filter(|c| !c.is_empty())
-        .collect();
+    for (entity, _, mut loader) in &mut loaders {
+        let roots = loader.roots().into_iter();
+        let selected = lod::select(roots, &viewpoints, |n| shown.contains(n), keep);
+        let nodes = loader.update_nodes(selected);
+        columns.extend(nodes.iter().filter_map(|n| n.chunk()).map(|c| (c.x, c.z)));
+        added.extend(loaded.assign(entity, nodes));
+    }
+
+    gen.retain(|x, z| columns.contains(&(x, z)));
 
-    chunks.sort_by_key(|chunk| (chunk.position().distance(pos) * 1000.0) as i64);
+    let positions: Vec<_> = viewpoints.iter().map(|(pos, _)| *pos).collect();
+    let camera = cameras.iter().next();
 
-    for chunk in chunks {
-        if !range.contains(chunk.position()) {
+    for node in added {
+        if loaded.chunks.contains_key(&node) {
+            // added by another loader
+            loaded.chunks.get_mut(&node).unwrap().refs += 1;
             continue;
         }
-        if loader.chunks.contains_key(&chunk) {
-            continue;
+
+        let revision = node.chunk().map(|c| edits.revision(&c)).unwrap_or(0);
+        let entity = match cache.mesh(&node, revision) {
+            Some(mesh) => {
+                let entity = spawn_terrain(&mut commands, &node);
+                let computed_chunk = ComputedChunk::new(None, entity, node.clone(), revision, mesh);
+                loaded.ready.push(computed_chunk);
+                entity
+            }
+            None => create_terrain(
+                &mut commands,
+                &mut scheduler,
+                node.clone(),
+                priority(&node, &positions, camera),
+            ),
+        };
+
+        loaded.pending.insert(node.clone());
+        loaded.chunks.insert(node, LoadedChunk { entity, refs: 1 });
+    }
+
+    loaded.retire(&mut commands, &mut scheduler);
+}
+
+pub fn modify_terrain_system(
+    mut scheduler: ResMut<ChunkScheduler>,
+    mut loaded: ResMut<LoadedChunks>,
+    mut modified: EventReader<ChunkModified>,
+) {
+    for ChunkModified(chunk) in modified.iter() {
+        let node = LodChunk::from(chunk.clone());
+        let entity = match loaded.entity(&node) {
+            Some(e) => e,
+            None => {
+                // kept by the selection now that it's modified, and meshed with the modifications when it's loaded
+                loaded.reselect = true;
+                continue;
+            }
+        };
+
+        scheduler.push(entity, node, URGENT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cave::CaveConfig, render::TerrainMaterial, voxel::Voxel};
+    use bevy::{asset::AssetPlugin, diagnostic::DiagnosticsPlugin};
+    use std::time::{Duration, Instant};
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin)
+            .add_plugin(DiagnosticsPlugin)
+            .add_asset::<Mesh>()
+            .add_asset::<TerrainMaterial>()
+            .init_resource::<TerrainRender>()
+            // caves are slow to generate in debug builds
+            .insert_resource(WorldGenSettings {
+                caves: CaveConfig {
+                    density: 0.0,
+                    overhang_amplitude: 0.0,
+                    ..default()
+                },
+                ..default()
+            })
+            .add_plugin(TerrainGenPlugin::default())
+            .add_system(request_terrain_system)
+            .add_system(modify_terrain_system)
+            .add_system(
+                dispatch_terrain_system
+                    .after(request_terrain_system)
+                    .after(modify_terrain_system),
+            )
+            .add_system(render_terrain_system.after(dispatch_terrain_system));
+        app
+    }
+
+    /// Run until every requested chunk is generated
+    fn settle(app: &mut App) {
+        let start = Instant::now();
+        loop {
+            app.update();
+            let scheduler = app.world.resource::<ChunkScheduler>();
+            if scheduler.queue_depth() == 0 && scheduler.in_flight() == 0 {
+                break;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(120),
+                "generation stalled"
+            );
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        // draw the last results and release the despawned chunks
+        app.update();
+    }
+
+    #[test]
+    fn assets_stay_bounded_while_walking() {
+        let mut app = app();
+        let shape = LoadShape {
+            radius: Chunk::size().x * (1 << MAX_LOD) as f32,
+            half_height: Some(Chunk::size().y * 4.0),
+        };
+        let loader = app
+            .world
+            .spawn()
+            .insert(Transform::from_xyz(0.0, 40.0, 0.0))
+            .insert(Loader::new(shape))
+            .id();
+
+        let mut max_chunks = 0;
+        for _ in 0..16 {
+            app.world
+                .get_mut::<Transform>(loader)
+                .unwrap()
+                .translation
+                .x += Chunk::size().x;
+            settle(&mut app);
+
+            let chunks = app.world.query::<&LodChunk>().iter(&app.world).count();
+            max_chunks = max_chunks.max(chunks);
+
+            // an opaque and a translucent mesh at most per chunk
+            let meshes = app.world.resource::<Assets<Mesh>>().len();
+            assert!(
+                meshes <= chunks * 2,
+                "{} meshes for {} chunks",
+                meshes,
+                chunks
+            );
+            // shared by every chunk
+            let materials = app.world.resource::<Assets<TerrainMaterial>>().len();
+            assert_eq!(materials, 2);
         }
 
-        let entity = create_terrain(&mut commands, (*sender).clone(), chunk.clone());
+        assert!(max_chunks > 0);
+    }
 
-        loader.chunks.insert(chunk, entity);
+    #[test]
+    fn digging_into_full_chunks_loads_them() {
+        let mut app = app();
+        let gen = app.world.resource::<TerrainGen>().clone();
+
+        // the topmost chunk generated full under the origin
+        let mut y = 0;
+        while gen.occupancy(Chunk::new(0, y, 0).voxel_range_y()) != Occupancy::Full {
+            y -= 1;
+        }
+        let full = Chunk::new(0, y, 0);
+        let (_, max_y) = full.voxel_range_y();
+
+        let shape = LoadShape {
+            radius: Chunk::size().x * (1 << MAX_LOD) as f32,
+            half_height: Some(Chunk::size().y * 4.0),
+        };
+        app.world
+            .spawn()
+            .insert(Transform::from_xyz(16.0, (max_y + 8) as f32, 16.0))
+            .insert(Loader::new(shape));
+        settle(&mut app);
+        assert!(app
+            .world
+            .resource::<LoadedChunks>()
+            .entity(&full.clone().into())
+            .is_none());
+
+        // dig the voxel right above the full chunk
+        let chunks = app
+            .world
+            .resource::<VoxelEdits>()
+            .set(0, max_y + 1, 0, Voxel::EMPTY);
+        assert!(chunks.contains(&full));
+        let mut events = app.world.resource_mut::<Events<ChunkModified>>();
+        for chunk in chunks {
+            events.send(ChunkModified(chunk));
+        }
+        settle(&mut app);
+
+        let entity = app
+            .world
+            .resource::<LoadedChunks>()
+            .entity(&full.into())
+            .expect("full chunk under the opening isn't loaded");
+        assert!(app.world.get::<Collider>(entity).is_some());
     }
 }
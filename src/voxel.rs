@@ -1,21 +1,28 @@
+use crate::block::{Block, BlockId};
 use block_mesh::{MergeVoxel, VoxelVisibility};
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub struct Voxel(Option<u64>);
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Voxel(BlockId);
 
 impl Voxel {
-    pub const EMPTY: Voxel = Voxel(None);
+    pub const EMPTY: Voxel = Voxel(BlockId::AIR);
 
-    pub fn new(value: u64) -> Self {
-        Self(Some(value))
+    pub fn new(id: BlockId) -> Self {
+        Self(id)
     }
 
-    pub fn value(&self) -> Option<u64> {
+    pub fn id(&self) -> BlockId {
         self.0
     }
 
+    pub fn block(&self) -> &'static Block {
+        self.0.block()
+    }
+
     pub fn is_empty(&self) -> bool {
-        self.0.is_none()
+        self.0 == BlockId::AIR
     }
 }
 
@@ -23,6 +30,8 @@ impl block_mesh::Voxel for Voxel {
     fn get_visibility(&self) -> VoxelVisibility {
         if self.is_empty() {
             VoxelVisibility::Empty
+        } else if self.block().transparent {
+            VoxelVisibility::Translucent
         } else {
             VoxelVisibility::Opaque
         }
@@ -30,7 +39,7 @@ impl block_mesh::Voxel for Voxel {
 }
 
 impl MergeVoxel for Voxel {
-    type MergeValue = Option<u64>;
+    type MergeValue = BlockId;
 
     fn merge_value(&self) -> Self::MergeValue {
         self.0